@@ -10,7 +10,6 @@ fn make_linear_curve<'a>(device: &'a Device) -> Geometry<'a> {
     {
         let mut verts = curve.vertex_buffer.as_mut_slice();
         let mut ids = curve.index_buffer.as_mut_slice();
-        let mut flags = curve.flag_buffer.as_mut_slice();
         verts[0] = Vec4::new(-5.0, 0.0, 0.0, 0.35);
         verts[1] = Vec4::new(-5.0, 4.0, -1.0, 0.25);
         verts[2] = Vec4::new(-5.0, 8.0, 2.0, 0.05);
@@ -18,9 +17,8 @@ fn make_linear_curve<'a>(device: &'a Device) -> Geometry<'a> {
         ids[0] = 0;
         ids[1] = 1;
         ids[2] = 2;
-        // flags[0] = 10;
-        // flags[1] = 1;
     }
+    curve.compute_flags();
     let mut curve_geo = Geometry::LinearCurve(curve);
     curve_geo.commit();
     curve_geo
@@ -103,7 +101,7 @@ fn make_hermite_curve<'a>(device: &'a Device) -> Geometry<'a> {
         normal_derivatives[1] = Vec3::new(0.4, 0.5, 1.0);
         normal_derivatives[2] = Vec3::new(0.4, 0.5, 1.0);
     }
-    let mut curve_geo = Geometry::HermiteCurve(curve);
+    let mut curve_geo = Geometry::HermiteCurve(Box::new(curve));
     curve_geo.commit();
     curve_geo
 }
@@ -165,16 +165,14 @@ fn main() {
             *p = 0;
         }
 
-        // Update scene transformations
+        // Update scene transformations, the changed instances are
+        // committed again when the scene is committed
         let (transforms, normal_transforms) = animate_instances(time, num_instances);
         let mut tfm_iter = transforms.iter();
         for g in scene.iter_mut() {
             if let Geometry::Instance(ref mut inst) = g.1 {
                 inst.set_transform(tfm_iter.next().expect("out of bounds tfm"));
             }
-            // A bit annoying here that we can't call the mut on the geometry
-            // part because we borred the inner instance piece as mutable
-            g.1.commit();
         }
 
         let rtscene = scene.commit();
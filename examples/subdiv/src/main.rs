@@ -20,7 +20,7 @@ fn main() {
         face.copy_from_slice(&[4u32]);
         idx.copy_from_slice(&[0u32, 1, 2, 3]);
     }
-    let mut subdiv_geom = Geometry::Subdiv(quad);
+    let mut subdiv_geom = Geometry::Subdiv(Box::new(quad));
     subdiv_geom.commit();
 
     let mut scene = Scene::new(&device);
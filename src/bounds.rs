@@ -0,0 +1,59 @@
+use std::f32;
+use ultraviolet::Vec3;
+
+use crate::sys;
+
+pub type Bounds = sys::RTCBounds;
+pub type LinearBounds = sys::RTCLinearBounds;
+
+impl Bounds {
+    /// Create a box spanning `lower` to `upper`
+    pub fn new(lower: Vec3, upper: Vec3) -> Bounds {
+        sys::RTCBounds {
+            lower_x: lower.x,
+            lower_y: lower.y,
+            lower_z: lower.z,
+            align0: 0.0,
+            upper_x: upper.x,
+            upper_y: upper.y,
+            upper_z: upper.z,
+            align1: 0.0,
+        }
+    }
+    /// Create an empty (inverted) box which can be grown with `union_point`
+    pub fn empty() -> Bounds {
+        Bounds::new(
+            Vec3::broadcast(f32::INFINITY),
+            Vec3::broadcast(f32::NEG_INFINITY),
+        )
+    }
+    pub fn lower(&self) -> Vec3 {
+        Vec3::new(self.lower_x, self.lower_y, self.lower_z)
+    }
+    pub fn upper(&self) -> Vec3 {
+        Vec3::new(self.upper_x, self.upper_y, self.upper_z)
+    }
+    pub fn union_point(&self, p: Vec3) -> Bounds {
+        Bounds::new(
+            self.lower().min_by_component(p),
+            self.upper().max_by_component(p),
+        )
+    }
+    pub fn union(&self, b: &Bounds) -> Bounds {
+        Bounds::new(
+            self.lower().min_by_component(b.lower()),
+            self.upper().max_by_component(b.upper()),
+        )
+    }
+}
+
+impl LinearBounds {
+    /// Get the bounds at `time`, linearly interpolated between the
+    /// bounds at the start and end of the time range.
+    pub fn at_time(&self, time: f32) -> Bounds {
+        Bounds::new(
+            self.bounds0.lower() * (1.0 - time) + self.bounds1.lower() * time,
+            self.bounds0.upper() * (1.0 - time) + self.bounds1.upper() * time,
+        )
+    }
+}
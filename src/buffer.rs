@@ -1,11 +1,17 @@
 use std::marker::PhantomData;
-use std::ops::{Index, IndexMut};
-use std::{mem, ptr};
+use std::ops::{Deref, DerefMut};
+use std::os::raw;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{mem, ptr, slice};
 
 use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::geometry::{geometry_data, mark_changed_if_alive};
 use crate::sys::*;
-use crate::BufferType;
+use crate::{BufferType, Error, Format};
 
+/// A geometry buffer slot the buffer is attached to
 #[derive(Copy, Clone)]
 struct BufferAttachment {
     geom: RTCGeometry,
@@ -13,36 +19,87 @@ struct BufferAttachment {
     slot: u32,
 }
 
-impl BufferAttachment {
-    fn none() -> BufferAttachment {
-        BufferAttachment {
-            geom: ptr::null_mut(),
-            buf_type: BufferType::VERTEX,
-            slot: std::u32::MAX,
-        }
+/// The attachments and change tracking shared by a buffer and its views,
+/// so writing to any of them updates all the geometries using the memory.
+pub(crate) struct BufferState {
+    /// The geometries and slots the buffer and its views are attached to,
+    /// which are retained so they can be updated when the buffer is changed
+    attachments: Mutex<Vec<BufferAttachment>>,
+    /// Set when the buffer is mapped mutably, so the change can be sent
+    /// to Embree with `update` when the geometry is next committed
+    dirty: AtomicBool,
+}
+
+impl BufferState {
+    fn new() -> Arc<BufferState> {
+        Arc::new(BufferState {
+            attachments: Mutex::new(Vec::new()),
+            dirty: AtomicBool::new(false),
+        })
     }
-    fn is_attached(&self) -> bool {
-        self.geom != ptr::null_mut()
+    /// Remove the attachments to the geometry `geom`, which is being
+    /// released, so it isn't updated after it's dropped
+    pub(crate) fn detach(&self, geom: RTCGeometry) {
+        let mut attachments = self.attachments.lock().unwrap();
+        attachments.retain(|a| {
+            if a.geom == geom {
+                unsafe {
+                    rtcReleaseGeometry(geom);
+                }
+            }
+            a.geom != geom
+        });
+    }
+}
+
+// The geometry handles are only used through Embree's thread safe API
+unsafe impl Send for BufferState {}
+unsafe impl Sync for BufferState {}
+
+impl Drop for BufferState {
+    fn drop(&mut self) {
+        let attachments = self.attachments.get_mut().unwrap();
+        for a in attachments.iter() {
+            unsafe {
+                rtcReleaseGeometry(a.geom);
+            }
+        }
     }
 }
 
-// TODO: To handle this nicely for sharing/re-using/changing buffer views
-// we basically need an API/struct for making buffer views of existing
-// larger buffers.
+/// A buffer of elements of type `T` which can be attached to geometry.
+/// A buffer can also be a view of part of a larger buffer made with
+/// `view`, with its own offset, stride and format.
 pub struct Buffer<'a, T> {
     device: &'a Device,
     pub(crate) handle: RTCBuffer,
-    // TODO: We need a list of RTCGeometry handles
-    // that we're attached to to mark buffers as updated on
-    // the geometries.
+    /// The size of the whole underlying buffer
     bytes: usize,
-    attachment: BufferAttachment,
+    /// The byte offset of the first element in the underlying buffer
+    offset: usize,
+    /// The bytes between elements
+    stride: usize,
+    /// The number of elements requested, without the padding
+    len: usize,
+    /// The number of elements in the slices returned by `as_slice`
+    slice_len: usize,
+    /// The format to attach a view with
+    format: Option<Format>,
+    state: Arc<BufferState>,
+    /// False for buffers sharing immutable memory from `shared` and views
+    /// made with `view`
+    writable: bool,
     marker: PhantomData<T>,
 }
 
+/// A view of part of a larger buffer made with `Buffer::view`. Views
+/// are buffers themselves, so they can be used anywhere a buffer is.
+pub type BufferView<'a, T> = Buffer<'a, T>;
+
 impl<'a, T> Buffer<'a, T> {
     /// Allocate a buffer with some raw capacity in bytes
     pub fn raw(device: &'a Device, bytes: usize) -> Buffer<'a, T> {
+        let len = bytes / mem::size_of::<T>();
         // Pad to a multiple of 16 bytes
         let bytes = if bytes % 16 == 0 {
             bytes
@@ -53,11 +110,22 @@ impl<'a, T> Buffer<'a, T> {
             device: device,
             handle: unsafe { rtcNewBuffer(device.handle, bytes) },
             bytes: bytes,
-            attachment: BufferAttachment::none(),
+            offset: 0,
+            stride: mem::size_of::<T>(),
+            len,
+            slice_len: bytes / mem::size_of::<T>(),
+            format: None,
+            state: BufferState::new(),
+            writable: true,
             marker: PhantomData,
         }
     }
+    /// Allocate a buffer with room for `len` elements, panics if the
+    /// buffer can't be allocated. See `try_new` for a fallible version.
     pub fn new(device: &'a Device, len: usize) -> Buffer<'a, T> {
+        Buffer::try_new(device, len).expect("Failed to allocate buffer")
+    }
+    pub fn try_new(device: &'a Device, len: usize) -> Result<Buffer<'a, T>> {
         let mut bytes = len * mem::size_of::<T>();
         // Pad to a multiple of 16 bytes
         bytes = if bytes % 16 == 0 {
@@ -65,35 +133,289 @@ impl<'a, T> Buffer<'a, T> {
         } else {
             bytes + bytes / 16
         };
-        Buffer {
+        device.clear_error();
+        let handle = unsafe { rtcNewBuffer(device.handle, bytes) };
+        if handle.is_null() {
+            return Err(device.error_or("Failed to allocate buffer"));
+        }
+        Ok(Buffer {
             device: device,
-            handle: unsafe { rtcNewBuffer(device.handle, bytes) },
+            handle,
             bytes: bytes,
-            attachment: BufferAttachment::none(),
+            offset: 0,
+            stride: mem::size_of::<T>(),
+            len,
+            slice_len: bytes / mem::size_of::<T>(),
+            format: None,
+            state: BufferState::new(),
+            writable: true,
             marker: PhantomData,
-        }
+        })
     }
-
-    pub fn as_mut_slice(&mut self) -> &'a mut [T] {
-        let len = self.bytes / mem::size_of::<T>();
+    /// Create a buffer of `len` elements which shares the memory of
+    /// `data` with Embree instead of allocating its own, e.g. to use
+    /// vertices which were loaded or memory mapped from a file directly.
+    /// The data is borrowed for the lifetime of the buffer and the
+    /// geometry it's attached to, and the buffer can't be written to,
+    /// see `shared_mut` for a writable version.
+    ///
+    /// Embree reads buffers with 16 byte SSE loads so `data` must have
+    /// at least 16 bytes from the start of element `len - 1`, e.g. a
+    /// `Vec3` vertex buffer needs one extra float of padding after the
+    /// last vertex. The data must also be aligned to 4 bytes, or to the
+    /// alignment of `T` if it's smaller.
+    pub fn shared(device: &'a Device, data: &'a [T], len: usize) -> Result<Buffer<'a, T>> {
         unsafe {
-            let slice = rtcGetBufferData(self.handle) as *mut T;
-            std::slice::from_raw_parts_mut(slice, len)
+            Buffer::from_shared(
+                device,
+                data.as_ptr() as *mut T,
+                mem::size_of_val(data),
+                len,
+                false,
+            )
         }
     }
-
-    pub fn as_slice(&mut self) -> &'a [T] {
-        let len = self.bytes / mem::size_of::<T>();
+    /// Create a buffer of `len` elements which shares the memory of `data`,
+    /// which can be written to through the buffer. See `shared` for the
+    /// padding and alignment requirements on `data`.
+    pub fn shared_mut(device: &'a Device, data: &'a mut [T], len: usize) -> Result<Buffer<'a, T>> {
+        unsafe { Buffer::from_shared(device, data.as_mut_ptr(), mem::size_of_val(data), len, true) }
+    }
+    unsafe fn from_shared(
+        device: &'a Device,
+        data: *mut T,
+        bytes: usize,
+        len: usize,
+        writable: bool,
+    ) -> Result<Buffer<'a, T>> {
+        let stride = mem::size_of::<T>();
+        let align = mem::align_of::<T>().min(4);
+        check_layout(data as usize, bytes, 0, stride, align, stride.max(16), len)?;
+        device.clear_error();
+        let handle = rtcNewSharedBuffer(device.handle, data as *mut raw::c_void, bytes);
+        if handle.is_null() {
+            return Err(device.error_or("Failed to create shared buffer"));
+        }
+        Ok(Buffer {
+            device,
+            handle,
+            bytes,
+            offset: 0,
+            stride,
+            len,
+            slice_len: bytes / stride,
+            format: None,
+            state: BufferState::new(),
+            writable,
+            marker: PhantomData,
+        })
+    }
+    /// Make a read-only view of `count` elements of type `U` which are
+    /// `stride` bytes apart, starting `offset` bytes into this buffer. The
+    /// view shares the buffer's memory, e.g. to attach the positions and
+    /// normals of interleaved vertices, and is attached to geometry with
+    /// `format`. Writes to the buffer update the geometries the view is
+    /// attached to, see `split_views` for writable views.
+    ///
+    /// Views with a stride other than the size of `U` can't be accessed
+    /// as slices, use `get` instead. The offset and stride must be aligned
+    /// to the size of the components of `format`, and for formats with 4
+    /// byte components 16 bytes must be readable from the start of the
+    /// last element.
+    pub fn view<U>(
+        &self,
+        offset: usize,
+        stride: usize,
+        count: usize,
+        format: Format,
+    ) -> Result<BufferView<'a, U>> {
+        self.make_view(self.offset + offset, stride, count, format)
+    }
+    /// Split the buffer into consecutive writable views of `counts[i]`
+    /// elements of type `U` each, e.g. to store the vertices of many meshes
+    /// in one buffer. The views don't overlap and the buffer is consumed,
+    /// so each view can be written to independently. See `view` for the
+    /// alignment requirements.
+    pub fn split_views<U>(
+        self,
+        counts: &[usize],
+        format: Format,
+    ) -> Result<Vec<BufferView<'a, U>>> {
+        let stride = mem::size_of::<U>();
+        let mut offset = self.offset;
+        let mut views = Vec::with_capacity(counts.len());
+        for &count in counts.iter() {
+            let mut view = self.make_view(offset, stride, count, format)?;
+            view.writable = self.writable;
+            views.push(view);
+            offset += count * stride;
+        }
+        Ok(views)
+    }
+    fn make_view<U>(
+        &self,
+        offset: usize,
+        stride: usize,
+        count: usize,
+        format: Format,
+    ) -> Result<BufferView<'a, U>> {
+        if stride < mem::size_of::<U>() {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!(
+                    "view stride {} is smaller than its {} byte elements",
+                    stride,
+                    mem::size_of::<U>()
+                ),
+            ));
+        }
+        let data = unsafe { rtcGetBufferData(self.handle) } as usize;
+        check_layout(
+            data,
+            self.bytes,
+            offset,
+            stride,
+            format_alignment(format),
+            format_read_size(format, mem::size_of::<U>()),
+            count,
+        )?;
         unsafe {
-            let slice = rtcGetBufferData(self.handle) as *const T;
-            std::slice::from_raw_parts(slice, len)
+            rtcRetainBuffer(self.handle);
         }
+        Ok(Buffer {
+            device: self.device,
+            handle: self.handle,
+            bytes: self.bytes,
+            offset,
+            stride,
+            len: count,
+            slice_len: count,
+            format: Some(format),
+            state: self.state.clone(),
+            writable: false,
+            marker: PhantomData,
+        })
+    }
+    /// Get the number of elements in the buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the byte offset of the buffer's first element, which is
+    /// non-zero for views
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Get the number of bytes between the buffer's elements
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    /// Get the format of a view, which is `None` for buffers
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+    /// Get element `i` of the buffer, which works for views of any stride
+    pub fn get(&self, i: usize) -> T
+    where
+        T: Copy,
+    {
+        assert!(i < self.len, "Buffer index {} out of bounds", i);
+        unsafe { ptr::read_unaligned(self.element_ptr(i)) }
+    }
+    /// Set element `i` of the buffer, which works for views of any stride.
+    /// The buffer is marked as changed, as with `as_mut_slice`.
+    pub fn set(&mut self, i: usize, value: T)
+    where
+        T: Copy,
+    {
+        assert!(self.writable, "Can't write to a read-only buffer");
+        assert!(i < self.len, "Buffer index {} out of bounds", i);
+        self.state.dirty.store(true, Ordering::Relaxed);
+        unsafe { ptr::write_unaligned(self.element_ptr(i), value) }
+    }
+    unsafe fn element_ptr(&self, i: usize) -> *mut T {
+        let data = rtcGetBufferData(self.handle) as *mut u8;
+        data.add(self.offset + i * self.stride) as *mut T
+    }
+    /// Get a pointer to the first element, checking that the elements
+    /// are laid out as a slice
+    fn slice_ptr(&self) -> *mut T {
+        let data = unsafe { rtcGetBufferData(self.handle) as *mut u8 }.wrapping_add(self.offset);
+        assert!(
+            self.stride == mem::size_of::<T>()
+                && (data as usize).is_multiple_of(mem::align_of::<T>()),
+            "Buffer view with stride {} can't be accessed as a slice",
+            self.stride
+        );
+        data as *mut T
     }
 
+    /// Map the buffer for writing. Writing through the returned
+    /// `MappedBuffer` marks the buffer as changed, and when it's dropped
+    /// each geometry the buffer is attached to is told the buffer was
+    /// changed and is committed again along with its scene.
+    pub fn map_mut(&mut self) -> MappedBuffer<'_, 'a, T> {
+        assert!(self.writable, "Can't write to a read-only buffer");
+        MappedBuffer { buffer: self }
+    }
+    /// Get the buffer's data for writing. The buffer is marked as changed
+    /// so the geometries it's attached to are updated when it or its scene
+    /// are next committed, the slice should not be held on to and written
+    /// to after the commit. See `map_mut` for a scoped alternative.
+    pub fn as_mut_slice(&mut self) -> &'a mut [T] {
+        assert!(self.writable, "Can't write to a read-only buffer");
+        self.state.dirty.store(true, Ordering::Relaxed);
+        unsafe { slice::from_raw_parts_mut(self.slice_ptr(), self.slice_len) }
+    }
+
+    pub fn as_slice(&self) -> &'a [T] {
+        unsafe { slice::from_raw_parts(self.slice_ptr(), self.slice_len) }
+    }
+
+    /// Check if the buffer was changed since it was last updated
+    pub fn is_dirty(&self) -> bool {
+        self.state.dirty.load(Ordering::Relaxed)
+    }
+    /// Tell Embree the buffer was changed on each geometry it or its views
+    /// are attached to if it's dirty, and mark the geometries as changed so
+    /// they're committed again. Returns true if the buffer was dirty.
+    pub(crate) fn update(&self) -> bool {
+        if !self.state.dirty.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        let attachments = self.state.attachments.lock().unwrap();
+        for a in attachments.iter() {
+            unsafe {
+                rtcUpdateGeometryBuffer(a.geom, a.buf_type, a.slot);
+                mark_changed_if_alive(a.geom);
+            }
+        }
+        true
+    }
+    /// Record that the buffer was attached to `slot` of the `buf_type`
+    /// buffers of the geometry `geom`
     pub(crate) fn set_attachment(&mut self, geom: RTCGeometry, buf_type: BufferType, slot: u32) {
-        self.attachment.geom = geom;
-        self.attachment.buf_type = buf_type;
-        self.attachment.slot = slot;
+        let mut attachments = self.state.attachments.lock().unwrap();
+        let attached = attachments
+            .iter()
+            .any(|a| a.geom == geom && a.buf_type == buf_type && a.slot == slot);
+        if !attached {
+            unsafe {
+                rtcRetainGeometry(geom);
+                let buffers = &mut geometry_data(geom).buffers;
+                if !buffers.iter().any(|b| ptr::eq(b.as_ptr(), &*self.state)) {
+                    buffers.push(Arc::downgrade(&self.state));
+                }
+            }
+            attachments.push(BufferAttachment {
+                geom,
+                buf_type,
+                slot,
+            });
+        }
     }
 }
 
@@ -106,3 +428,206 @@ impl<'a, T> Drop for Buffer<'a, T> {
 }
 
 unsafe impl<'a, T> Sync for Buffer<'a, T> {}
+
+/// A buffer mapped for writing with `Buffer::map_mut`, which updates
+/// the geometries the buffer is attached to when it's dropped.
+pub struct MappedBuffer<'b, 'a, T> {
+    buffer: &'b mut Buffer<'a, T>,
+}
+
+impl<'b, 'a, T> Deref for MappedBuffer<'b, 'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.buffer.slice_ptr(), self.buffer.len) }
+    }
+}
+
+impl<'b, 'a, T> DerefMut for MappedBuffer<'b, 'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.buffer.state.dirty.store(true, Ordering::Relaxed);
+        unsafe { slice::from_raw_parts_mut(self.buffer.slice_ptr(), self.buffer.len) }
+    }
+}
+
+impl<'b, 'a, T> Drop for MappedBuffer<'b, 'a, T> {
+    fn drop(&mut self) {
+        self.buffer.update();
+    }
+}
+
+/// Get the alignment Embree requires of data in `format`, formats with
+/// byte or short components only need those aligned
+pub(crate) fn format_alignment(format: Format) -> usize {
+    // The component type is in the top bits of the format
+    match format as u32 >> 12 {
+        1 | 2 => 1,
+        3 | 4 => 2,
+        _ => 4,
+    }
+}
+
+/// Get the number of bytes Embree may read from the start of an element
+/// of `elem_size` bytes in `format`. Float and int data is read with 16
+/// byte SSE loads, byte and short data only as the components of the
+/// format, whose count is in the low bits of the format.
+pub(crate) fn format_read_size(format: Format, elem_size: usize) -> usize {
+    let align = format_alignment(format);
+    if align < 4 {
+        elem_size.max((format as u32 & 0xf) as usize * align)
+    } else {
+        elem_size.max(16)
+    }
+}
+
+/// Check that `count` elements `stride` bytes apart starting `offset`
+/// bytes into the memory at `ptr` of `bytes` size meet Embree's
+/// requirements: the start and stride must be aligned to `align` bytes,
+/// and `read_size` bytes must be readable from the start of the last element.
+pub(crate) fn check_layout(
+    ptr: usize,
+    bytes: usize,
+    offset: usize,
+    stride: usize,
+    align: usize,
+    read_size: usize,
+    count: usize,
+) -> Result<()> {
+    if !ptr.wrapping_add(offset).is_multiple_of(align) || !stride.is_multiple_of(align) {
+        return Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            &format!(
+                "buffer data and stride must be aligned to {} bytes, got address {:#x} and stride {}",
+                align,
+                ptr.wrapping_add(offset),
+                stride
+            ),
+        ));
+    }
+    let end = (count.max(1) - 1)
+        .checked_mul(stride)
+        .and_then(|n| n.checked_add(offset))
+        .and_then(|n| n.checked_add(read_size));
+    if count > 0 && end.is_none_or(|end| end > bytes) {
+        return Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            &format!(
+                "buffer of {} bytes is too small for {} elements, {} bytes must be readable from the start of the last element",
+                bytes, count, read_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_layout() {
+    // 4 Vec3 vertices need an extra float of padding
+    assert!(check_layout(0, 48, 0, 12, 4, 16, 4).is_err());
+    assert!(check_layout(0, 52, 0, 12, 4, 16, 4).is_ok());
+    assert!(check_layout(2, 64, 0, 12, 4, 16, 4).is_err());
+    assert!(check_layout(0, 64, 0, 6, 4, 16, 4).is_err());
+    assert!(check_layout(0, 0, 0, 12, 4, 16, 0).is_ok());
+    // Byte flags only need to be aligned to bytes
+    assert_eq!(format_alignment(Format::UCHAR), 1);
+    assert_eq!(format_alignment(Format::FLOAT3), 4);
+    assert!(check_layout(0, 16, 3, 1, 1, 1, 13).is_ok());
+    assert_eq!(format_read_size(Format::UCHAR, 1), 1);
+    assert_eq!(format_read_size(Format::USHORT2, 1), 4);
+    assert_eq!(format_read_size(Format::FLOAT3, 12), 16);
+    // Offsets and counts which overflow are rejected
+    assert!(check_layout(0, 64, usize::MAX - 8, 4, 4, 16, 1).is_err());
+    assert!(check_layout(0, 64, 0, 16, 4, 16, usize::MAX).is_err());
+}
+
+/// Update each of the `buffers`, returns true if any of them were changed
+pub(crate) fn update_all<'b, 'a: 'b, T: 'b, I>(buffers: I) -> bool
+where
+    I: IntoIterator<Item = &'b Buffer<'a, T>>,
+{
+    buffers
+        .into_iter()
+        .fold(false, |changed, b| b.update() | changed)
+}
+
+#[test]
+fn test_buffer_tracking() {
+    use crate::geometry::Geometry;
+    use crate::triangle_mesh::TriangleMesh;
+    use ultraviolet::Vec3;
+
+    let device = Device::new();
+    let mut geometry = Geometry::Triangle(TriangleMesh::unanimated(&device, 1, 3));
+    geometry.take_changed();
+    let mesh = match geometry {
+        Geometry::Triangle(ref mut mesh) => mesh,
+        _ => unreachable!(),
+    };
+    // Writes through map_mut mark the buffer dirty until it's unmapped,
+    // which updates the geometry
+    {
+        let mut vertices = mesh.vertex_buffer.map_mut();
+        vertices[0] = Vec3::one();
+        assert!(vertices.buffer.is_dirty());
+    }
+    assert!(!mesh.vertex_buffer.is_dirty());
+    assert!(geometry.take_changed());
+
+    let mesh = match geometry {
+        Geometry::Triangle(ref mut mesh) => mesh,
+        _ => unreachable!(),
+    };
+    mesh.vertex_buffer.as_mut_slice()[1] = Vec3::one();
+    assert!(mesh.vertex_buffer.is_dirty());
+    assert!(update_all(Some(&mesh.vertex_buffer)));
+    assert!(!mesh.vertex_buffer.is_dirty());
+    assert!(!update_all(Some(&mesh.vertex_buffer)));
+    assert!(geometry.take_changed());
+}
+
+#[test]
+fn test_detach_dropped_geometry() {
+    use crate::geometry::Geometry;
+    use crate::triangle_mesh::TriangleMesh;
+    use ultraviolet::Vec3;
+
+    let device = Device::new();
+    let mut buffer = Buffer::<Vec3>::new(&device, 3);
+    let mut geometry = Geometry::Triangle(TriangleMesh::unanimated(&device, 1, 3));
+    geometry
+        .set_buffer(&mut buffer, BufferType::VERTEX, 0, Format::FLOAT3)
+        .unwrap();
+    assert_eq!(buffer.state.attachments.lock().unwrap().len(), 1);
+    drop(geometry);
+    assert!(buffer.state.attachments.lock().unwrap().is_empty());
+    // Writing to the buffer no longer touches the dropped geometry
+    buffer.map_mut()[0] = Vec3::one();
+}
+
+#[test]
+fn test_split_views() {
+    use ultraviolet::Vec3;
+
+    let device = Device::new();
+    let buffer = Buffer::<u32>::new(&device, 64);
+    let counts = [3, 5, 2];
+    let mut views = buffer.split_views::<Vec3>(&counts, Format::FLOAT3).unwrap();
+    let mut end = 0;
+    for (view, &count) in views.iter().zip(counts.iter()) {
+        assert_eq!(view.len(), count);
+        assert_eq!(view.stride(), 12);
+        assert_eq!(view.offset(), end);
+        end = view.offset() + count * view.stride();
+    }
+    // Writing to each view doesn't change the others
+    for (i, view) in views.iter_mut().enumerate() {
+        for v in view.as_mut_slice().iter_mut() {
+            *v = Vec3::broadcast(i as f32);
+        }
+    }
+    for (i, view) in views.iter().enumerate() {
+        assert!(view
+            .as_slice()
+            .iter()
+            .all(|&v| v == Vec3::broadcast(i as f32)));
+    }
+}
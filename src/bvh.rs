@@ -0,0 +1,285 @@
+use std::os::raw;
+use std::{mem, ptr, slice};
+
+use ultraviolet::Vec3;
+
+use crate::bounds::Bounds;
+use crate::catch_unwind_abort;
+use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::sys::*;
+use crate::{BuildFlags, BuildQuality, Error};
+
+pub type BuildPrimitive = RTCBuildPrimitive;
+
+impl BuildPrimitive {
+    /// Create a build primitive with the bounds `bounds` for the
+    /// primitive `prim_id` of the geometry `geom_id`
+    pub fn new(bounds: &Bounds, geom_id: u32, prim_id: u32) -> BuildPrimitive {
+        RTCBuildPrimitive {
+            lower_x: bounds.lower_x,
+            lower_y: bounds.lower_y,
+            lower_z: bounds.lower_z,
+            geomID: geom_id,
+            upper_x: bounds.upper_x,
+            upper_y: bounds.upper_y,
+            upper_z: bounds.upper_z,
+            primID: prim_id,
+        }
+    }
+    pub fn bounds(&self) -> Bounds {
+        Bounds::new(
+            Vec3::new(self.lower_x, self.lower_y, self.lower_z),
+            Vec3::new(self.upper_x, self.upper_y, self.upper_z),
+        )
+    }
+    pub fn geom_id(&self) -> u32 {
+        self.geomID
+    }
+    pub fn prim_id(&self) -> u32 {
+        self.primID
+    }
+}
+
+/// The settings for building a BVH with `Bvh::build`, defaults to
+/// the same values as Embree's `rtcDefaultBuildArguments`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BvhConfig {
+    quality: BuildQuality,
+    flags: BuildFlags,
+    max_branching_factor: u32,
+    max_depth: u32,
+    sah_block_size: u32,
+    min_leaf_size: u32,
+    max_leaf_size: u32,
+    traversal_cost: f32,
+    intersection_cost: f32,
+}
+
+impl Default for BvhConfig {
+    fn default() -> BvhConfig {
+        BvhConfig {
+            quality: BuildQuality::MEDIUM,
+            flags: BuildFlags::NONE,
+            max_branching_factor: 2,
+            max_depth: 32,
+            sah_block_size: 1,
+            min_leaf_size: 1,
+            max_leaf_size: RTCBuildConstants_RTC_BUILD_MAX_PRIMITIVES_PER_LEAF,
+            traversal_cost: 1.0,
+            intersection_cost: 1.0,
+        }
+    }
+}
+
+impl BvhConfig {
+    pub fn new() -> BvhConfig {
+        BvhConfig::default()
+    }
+    /// Set the build quality, `LOW` uses a fast Morton code builder
+    /// and `MEDIUM` and `HIGH` use a binned SAH builder.
+    pub fn quality(mut self, quality: BuildQuality) -> BvhConfig {
+        self.quality = quality;
+        self
+    }
+    pub fn flags(mut self, flags: BuildFlags) -> BvhConfig {
+        self.flags = flags;
+        self
+    }
+    /// Set the maximum number of children of each inner node
+    pub fn max_branching_factor(mut self, factor: u32) -> BvhConfig {
+        self.max_branching_factor = factor;
+        self
+    }
+    /// Set the depth after which leaves are created regardless of their size
+    pub fn max_depth(mut self, depth: u32) -> BvhConfig {
+        self.max_depth = depth;
+        self
+    }
+    /// Set the block size the SAH cost of leaves is rounded up to, e.g.
+    /// the SIMD width used to intersect the primitives in a leaf
+    pub fn sah_block_size(mut self, size: u32) -> BvhConfig {
+        self.sah_block_size = size;
+        self
+    }
+    /// Set the minimum and maximum number of primitives in each leaf
+    pub fn leaf_size(mut self, min: u32, max: u32) -> BvhConfig {
+        self.min_leaf_size = min;
+        self.max_leaf_size = max;
+        self
+    }
+    /// Set the SAH cost of traversing a node and intersecting a primitive
+    pub fn sah_costs(mut self, traversal: f32, intersection: f32) -> BvhConfig {
+        self.traversal_cost = traversal;
+        self.intersection_cost = intersection;
+        self
+    }
+}
+
+/// The callbacks building the user's nodes, passed as the user
+/// pointer of the build.
+struct BuildFns<'f, 'b, N> {
+    create_node: &'f (dyn Fn(u32) -> N + Sync),
+    set_children: &'f (dyn Fn(&mut N, &[&'b N]) + Sync),
+    set_bounds: &'f (dyn Fn(&mut N, &[&Bounds]) + Sync),
+    create_leaf: &'f (dyn Fn(&[BuildPrimitive]) -> N + Sync),
+}
+
+/// A BVH built by Embree's generic BVH builder over the user's own
+/// node types. The nodes are allocated in an arena owned by the `Bvh`
+/// which is kept until the BVH is rebuilt or dropped.
+pub struct Bvh<'a> {
+    device: &'a Device,
+    handle: RTCBVH,
+}
+
+impl<'a> Bvh<'a> {
+    pub fn new(device: &'a Device) -> Bvh<'a> {
+        Bvh::try_new(device).expect("Failed to create Bvh")
+    }
+    pub fn try_new(device: &'a Device) -> Result<Bvh<'a>> {
+        device.clear_error();
+        let h = unsafe { rtcNewBVH(device.handle) };
+        if h.is_null() {
+            return Err(device.error_or("Failed to create Bvh"));
+        }
+        Ok(Bvh { device, handle: h })
+    }
+    /// Build a BVH over `primitives` and return its root node. The
+    /// primitives array may be reordered by the build.
+    ///
+    /// The callbacks are called from Embree's build threads to create the
+    /// nodes: `create_node` creates an inner node with the given number
+    /// of children, which are then passed to `set_children` along with
+    /// their bounds to `set_bounds`, and `create_leaf` creates a leaf
+    /// node for the primitives passed.
+    ///
+    /// The nodes are stored in the BVH's arena and borrow the BVH, so it
+    /// can't be rebuilt or dropped while they're in use. Note that the
+    /// nodes are never dropped, they should not own other resources.
+    pub fn build<'b, N, CN, SC, SB, CL>(
+        &'b mut self,
+        config: &BvhConfig,
+        primitives: &mut [BuildPrimitive],
+        create_node: CN,
+        set_children: SC,
+        set_bounds: SB,
+        create_leaf: CL,
+    ) -> Result<&'b N>
+    where
+        N: Send + Sync + 'b,
+        CN: Fn(u32) -> N + Sync,
+        SC: Fn(&mut N, &[&'b N]) + Sync,
+        SB: Fn(&mut N, &[&Bounds]) + Sync,
+        CL: Fn(&[BuildPrimitive]) -> N + Sync,
+    {
+        let fns = BuildFns {
+            create_node: &create_node,
+            set_children: &set_children,
+            set_bounds: &set_bounds,
+            create_leaf: &create_leaf,
+        };
+        let args = RTCBuildArguments {
+            byteSize: mem::size_of::<RTCBuildArguments>(),
+            buildQuality: config.quality,
+            buildFlags: config.flags,
+            maxBranchingFactor: config.max_branching_factor,
+            maxDepth: config.max_depth,
+            sahBlockSize: config.sah_block_size,
+            minLeafSize: config.min_leaf_size,
+            maxLeafSize: config.max_leaf_size,
+            traversalCost: config.traversal_cost,
+            intersectionCost: config.intersection_cost,
+            bvh: self.handle,
+            primitives: primitives.as_mut_ptr(),
+            primitiveCount: primitives.len(),
+            primitiveArrayCapacity: primitives.len(),
+            createNode: Some(create_node_function::<N>),
+            setNodeChildren: Some(set_node_children_function::<N>),
+            setNodeBounds: Some(set_node_bounds_function::<N>),
+            createLeaf: Some(create_leaf_function::<N>),
+            splitPrimitive: None,
+            buildProgress: None,
+            userPtr: &fns as *const BuildFns<'_, '_, N> as *mut raw::c_void,
+        };
+        let root = unsafe { rtcBuildBVH(&args) };
+        self.device.get_error()?;
+        if root.is_null() {
+            Err(EmbreeError::new(
+                Error::UNKNOWN,
+                "BVH build returned no root",
+            ))
+        } else {
+            Ok(unsafe { &*(root as *const N) })
+        }
+    }
+}
+
+impl<'a> Drop for Bvh<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            rtcReleaseBVH(self.handle);
+        }
+    }
+}
+
+unsafe impl<'a> Sync for Bvh<'a> {}
+
+/// Allocate `node` in the BVH's arena with the thread local allocator
+unsafe fn alloc_node<N>(allocator: RTCThreadLocalAllocator, node: N) -> *mut raw::c_void {
+    let ptr =
+        rtcThreadLocalAlloc(allocator, mem::size_of::<N>().max(1), mem::align_of::<N>()) as *mut N;
+    if ptr.is_null() {
+        panic!("Failed to allocate BVH node");
+    }
+    ptr::write(ptr, node);
+    ptr as *mut raw::c_void
+}
+
+unsafe extern "C" fn create_node_function<N>(
+    allocator: RTCThreadLocalAllocator,
+    child_count: raw::c_uint,
+    user_ptr: *mut raw::c_void,
+) -> *mut raw::c_void {
+    let fns = &*(user_ptr as *const BuildFns<'_, '_, N>);
+    catch_unwind_abort(|| alloc_node(allocator, (fns.create_node)(child_count)))
+}
+
+unsafe extern "C" fn set_node_children_function<N>(
+    node: *mut raw::c_void,
+    children: *mut *mut raw::c_void,
+    child_count: raw::c_uint,
+    user_ptr: *mut raw::c_void,
+) {
+    let fns = &*(user_ptr as *const BuildFns<'_, '_, N>);
+    let node = &mut *(node as *mut N);
+    let children = slice::from_raw_parts(children as *const &N, child_count as usize);
+    catch_unwind_abort(|| (fns.set_children)(node, children));
+}
+
+unsafe extern "C" fn set_node_bounds_function<N>(
+    node: *mut raw::c_void,
+    bounds: *mut *const RTCBounds,
+    child_count: raw::c_uint,
+    user_ptr: *mut raw::c_void,
+) {
+    let fns = &*(user_ptr as *const BuildFns<'_, '_, N>);
+    let node = &mut *(node as *mut N);
+    let bounds = slice::from_raw_parts(bounds as *const &Bounds, child_count as usize);
+    catch_unwind_abort(|| (fns.set_bounds)(node, bounds));
+}
+
+unsafe extern "C" fn create_leaf_function<N>(
+    allocator: RTCThreadLocalAllocator,
+    primitives: *const RTCBuildPrimitive,
+    primitive_count: size_t,
+    user_ptr: *mut raw::c_void,
+) -> *mut raw::c_void {
+    let fns = &*(user_ptr as *const BuildFns<'_, '_, N>);
+    let primitives = if primitives.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(primitives, primitive_count)
+    };
+    catch_unwind_abort(|| alloc_node(allocator, (fns.create_leaf)(primitives)))
+}
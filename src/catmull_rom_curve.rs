@@ -1,17 +1,28 @@
+use std::ptr;
+
 use ultraviolet::{Vec2, Vec3, Vec4};
 
-use crate::buffer::Buffer;
+use crate::buffer::{update_all, Buffer};
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::error::Result;
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, new_geometry,
+};
 use crate::sys::*;
+use crate::vertex_attribute::VertexAttribute;
 use crate::{BufferType, CurveType, Format, GeometryType};
 
 pub struct CatmullRomCurve<'a> {
-    device: &'a Device,
+    pub(crate) device: &'a Device,
     pub(crate) handle: RTCGeometry,
     pub vertex_buffer: Buffer<'a, Vec4>,
+    /// The vertex buffers for the time steps after the first of an animated curve
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec4>>,
     pub index_buffer: Buffer<'a, u32>,
     pub normal_buffer: Option<Buffer<'a, Vec3>>,
+    pub motion_normal_buffers: Vec<Buffer<'a, Vec3>>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
 }
 
 impl<'a> CatmullRomCurve<'a> {
@@ -21,12 +32,22 @@ impl<'a> CatmullRomCurve<'a> {
         num_verts: usize,
         use_normals: bool,
     ) -> CatmullRomCurve<'a> {
-        CatmullRomCurve::unanimated(
+        CatmullRomCurve::try_flat(device, num_segments, num_verts, use_normals)
+            .expect("Failed to create CatmullRomCurve")
+    }
+    pub fn try_flat(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        use_normals: bool,
+    ) -> Result<CatmullRomCurve<'a>> {
+        CatmullRomCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::Flat,
             use_normals,
+            1,
         )
     }
     pub fn round(
@@ -35,12 +56,22 @@ impl<'a> CatmullRomCurve<'a> {
         num_verts: usize,
         use_normals: bool,
     ) -> CatmullRomCurve<'a> {
-        CatmullRomCurve::unanimated(
+        CatmullRomCurve::try_round(device, num_segments, num_verts, use_normals)
+            .expect("Failed to create CatmullRomCurve")
+    }
+    pub fn try_round(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        use_normals: bool,
+    ) -> Result<CatmullRomCurve<'a>> {
+        CatmullRomCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::Round,
             use_normals,
+            1,
         )
     }
     pub fn normal_oriented(
@@ -48,89 +79,168 @@ impl<'a> CatmullRomCurve<'a> {
         num_segments: usize,
         num_verts: usize,
     ) -> CatmullRomCurve<'a> {
-        CatmullRomCurve::unanimated(
+        CatmullRomCurve::try_normal_oriented(device, num_segments, num_verts)
+            .expect("Failed to create CatmullRomCurve")
+    }
+    pub fn try_normal_oriented(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+    ) -> Result<CatmullRomCurve<'a>> {
+        CatmullRomCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::NormalOriented,
             true,
+            1,
         )
     }
 
-    fn unanimated(
+    /// Create a curve with `time_steps` vertex buffers for motion blur,
+    /// panics if the curve can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
         device: &'a Device,
         num_segments: usize,
         num_verts: usize,
         curve_type: CurveType,
         use_normals: bool,
+        time_steps: u32,
     ) -> CatmullRomCurve<'a> {
-        let h: RTCGeometry;
-        match curve_type {
-            CurveType::NormalOriented => {
-                h = unsafe {
-                    rtcNewGeometry(
-                        device.handle,
-                        GeometryType::NORMAL_ORIENTED_CATMULL_ROM_CURVE,
-                    )
-                }
-            }
-            CurveType::Round => {
-                h = unsafe { rtcNewGeometry(device.handle, GeometryType::ROUND_CATMULL_ROM_CURVE) }
-            }
-            _ => h = unsafe { rtcNewGeometry(device.handle, GeometryType::FLAT_CATMULL_ROM_CURVE) },
+        CatmullRomCurve::try_animated(
+            device,
+            num_segments,
+            num_verts,
+            curve_type,
+            use_normals,
+            time_steps,
+        )
+        .expect("Failed to create CatmullRomCurve")
+    }
+    /// Create a curve with `time_steps` vertex buffers (and normal
+    /// buffers if used) for motion blur. The buffers of the first time
+    /// step are in `vertex_buffer` and `normal_buffer` and the following
+    /// ones in `motion_vertex_buffers` and `motion_normal_buffers`.
+    pub fn try_animated(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        curve_type: CurveType,
+        use_normals: bool,
+        time_steps: u32,
+    ) -> Result<CatmullRomCurve<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let index_buffer = Buffer::try_new(device, num_segments)?;
+        let normal_buffer = if use_normals {
+            Some(Buffer::try_new(device, num_verts)?)
+        } else {
+            None
         };
-        let mut vertex_buffer = Buffer::new(device, num_verts);
-        let mut index_buffer = Buffer::new(device, num_segments);
-        let mut normal_buffer = None;
-
+        let motion_normal_buffers = if use_normals {
+            (1..time_steps)
+                .map(|_| Buffer::try_new(device, num_verts))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        CatmullRomCurve {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers,
+            index_buffer,
+            normal_buffer,
+            motion_normal_buffers,
+            vertex_attributes: Vec::new(),
+        }
+        .create(curve_type)
+    }
+    /// Create a curve of `curve_type` using existing buffers, e.g. buffers
+    /// sharing the user's memory made with `Buffer::shared` or views of
+    /// a larger buffer made with `Buffer::view`. Normal oriented curves
+    /// need a normal buffer.
+    pub fn from_buffers(
+        device: &'a Device,
+        curve_type: CurveType,
+        vertex_buffer: Buffer<'a, Vec4>,
+        index_buffer: Buffer<'a, u32>,
+        normal_buffer: Option<Buffer<'a, Vec3>>,
+    ) -> Result<CatmullRomCurve<'a>> {
+        CatmullRomCurve {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers: Vec::new(),
+            index_buffer,
+            normal_buffer,
+            motion_normal_buffers: Vec::new(),
+            vertex_attributes: Vec::new(),
+        }
+        .create(curve_type)
+    }
+    /// Create the Embree geometry of `curve_type` for the curve's buffers
+    /// and attach them to it
+    fn create(mut self, curve_type: CurveType) -> Result<CatmullRomCurve<'a>> {
+        let geometry_type = match curve_type {
+            CurveType::NormalOriented => GeometryType::NORMAL_ORIENTED_CATMULL_ROM_CURVE,
+            CurveType::Round => GeometryType::ROUND_CATMULL_ROM_CURVE,
+            _ => GeometryType::FLAT_CATMULL_ROM_CURVE,
+        };
+        let h = unsafe { new_geometry(self.device, geometry_type)? };
+        let num_verts = self.vertex_buffer.len();
+        let time_steps = self.motion_vertex_buffers.len() as u32 + 1;
         unsafe {
-            rtcSetGeometryBuffer(
+            attach_buffer(
                 h,
+                &mut self.vertex_buffer,
                 BufferType::VERTEX,
                 0,
                 Format::FLOAT4,
-                vertex_buffer.handle,
-                0,
-                16,
+            );
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
+                h,
+                &mut self.motion_vertex_buffers,
+                BufferType::VERTEX,
+                Format::FLOAT4,
                 num_verts,
             );
-            vertex_buffer.set_attachment(h, BufferType::VERTEX, 0);
-
-            rtcSetGeometryBuffer(
+            attach_buffer(
                 h,
+                &mut self.index_buffer,
                 BufferType::INDEX,
                 0,
                 Format::UINT,
-                index_buffer.handle,
-                0,
-                4,
-                num_segments,
             );
-            index_buffer.set_attachment(h, BufferType::INDEX, 0);
-
-            if use_normals {
-                let mut temp_normal_buffer = Buffer::new(device, num_verts);
-                rtcSetGeometryBuffer(
+            if let Some(ref mut normal_buffer) = self.normal_buffer {
+                attach_buffer(h, normal_buffer, BufferType::NORMAL, 0, Format::FLOAT3);
+                attach_time_step_buffers(
                     h,
+                    &mut self.motion_normal_buffers,
                     BufferType::NORMAL,
-                    0,
                     Format::FLOAT3,
-                    temp_normal_buffer.handle,
-                    0,
-                    12,
                     num_verts,
                 );
-                temp_normal_buffer.set_attachment(h, BufferType::NORMAL, 0);
-                normal_buffer = Some(temp_normal_buffer);
             }
+            check_new_geometry(self.device, h)?;
         }
-        CatmullRomCurve {
-            device: device,
-            handle: h,
-            vertex_buffer: vertex_buffer,
-            index_buffer: index_buffer,
-            normal_buffer: normal_buffer,
-        }
+        self.handle = h;
+        Ok(self)
+    }
+    /// Update the buffers changed since the curve was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | self.index_buffer.update()
+            | update_all(&self.normal_buffer)
+            | update_all(&self.motion_normal_buffers)
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
     }
 }
 
@@ -0,0 +1,109 @@
+use std::os::raw;
+use std::slice;
+
+use ultraviolet::Vec3;
+
+use crate::catch_unwind_abort;
+use crate::sys;
+
+pub type Collision = sys::RTCCollision;
+
+impl Collision {
+    /// The (geometry ID, primitive ID) of the colliding primitive in the first scene
+    pub fn first(&self) -> (u32, u32) {
+        (self.geomID0, self.primID0)
+    }
+    /// The (geometry ID, primitive ID) of the colliding primitive in the second scene
+    pub fn second(&self) -> (u32, u32) {
+        (self.geomID1, self.primID1)
+    }
+}
+
+pub(crate) type CollideFn<'a> = dyn Fn(&[Collision]) + Sync + 'a;
+
+pub(crate) unsafe extern "C" fn collide_function(
+    user_ptr: *mut raw::c_void,
+    collisions: *mut sys::RTCCollision,
+    num_collisions: raw::c_uint,
+) {
+    let callback = &*(user_ptr as *const &CollideFn);
+    let collisions = if collisions.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(collisions as *const Collision, num_collisions as usize)
+    };
+    catch_unwind_abort(|| callback(collisions));
+}
+
+/// Test if the triangles `a` and `b` overlap, e.g. to do exact tests
+/// on the pairs of primitives found by `CommittedScene::collide`.
+/// Uses the separating axis test, with the in-plane axes of the triangles
+/// included so coplanar triangles are handled as well.
+pub fn triangles_overlap(a: &[Vec3; 3], b: &[Vec3; 3]) -> bool {
+    let edges_a = [a[1] - a[0], a[2] - a[1], a[0] - a[2]];
+    let edges_b = [b[1] - b[0], b[2] - b[1], b[0] - b[2]];
+    let normal_a = edges_a[0].cross(edges_a[1]);
+    let normal_b = edges_b[0].cross(edges_b[1]);
+
+    let mut axes = Vec::with_capacity(17);
+    axes.push(normal_a);
+    axes.push(normal_b);
+    for ea in edges_a.iter() {
+        for eb in edges_b.iter() {
+            axes.push(ea.cross(*eb));
+        }
+    }
+    for e in edges_a.iter() {
+        axes.push(normal_a.cross(*e));
+    }
+    for e in edges_b.iter() {
+        axes.push(normal_b.cross(*e));
+    }
+
+    let project = |tri: &[Vec3; 3], axis: Vec3| {
+        let d = [tri[0].dot(axis), tri[1].dot(axis), tri[2].dot(axis)];
+        (d[0].min(d[1]).min(d[2]), d[0].max(d[1]).max(d[2]))
+    };
+    for axis in axes.iter() {
+        // Skip degenerate axes from parallel edges
+        if axis.mag_sq() < 1e-12 {
+            continue;
+        }
+        let (min_a, max_a) = project(a, *axis);
+        let (min_b, max_b) = project(b, *axis);
+        if max_a < min_b || max_b < min_a {
+            return false;
+        }
+    }
+    true
+}
+
+#[test]
+fn test_triangles_overlap() {
+    let a = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ];
+    // Piercing a through its face
+    let b = [
+        Vec3::new(0.25, 0.25, -1.0),
+        Vec3::new(0.25, 0.25, 1.0),
+        Vec3::new(0.5, 0.0, 1.0),
+    ];
+    assert!(triangles_overlap(&a, &b));
+    // Parallel and offset
+    let c = [
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 1.0),
+    ];
+    assert!(!triangles_overlap(&a, &c));
+    // Coplanar but separated in the plane
+    let d = [
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(2.0, 1.0, 0.0),
+        Vec3::new(1.0, 2.0, 0.0),
+    ];
+    assert!(!triangles_overlap(&a, &d));
+}
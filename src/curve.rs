@@ -1,6 +1,354 @@
+use ultraviolet::Vec4;
+
+use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::geometry::Geometry;
+use crate::{BezierCurve, BsplineCurve, CatmullRomCurve, Error, HermiteCurve, LinearCurve};
+
 pub enum CurveType {
     Flat,
     NormalOriented,
     Round,
     Cone,
 }
+
+/// The basis of a curve geometry
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CurveBasis {
+    Linear,
+    Bezier,
+    Bspline,
+    CatmullRom,
+    Hermite,
+}
+
+/// Convert the 4 control points of a cubic segment to the Bézier control
+/// points of the same curve. Panics if `basis` isn't Bézier, B-spline or
+/// Catmull-Rom.
+fn to_bezier(p: [Vec4; 4], basis: CurveBasis) -> [Vec4; 4] {
+    match basis {
+        CurveBasis::Bezier => p,
+        CurveBasis::Bspline => [
+            (p[0] + p[1] * 4.0 + p[2]) / 6.0,
+            (p[1] * 2.0 + p[2]) / 3.0,
+            (p[1] + p[2] * 2.0) / 3.0,
+            (p[1] + p[2] * 4.0 + p[3]) / 6.0,
+        ],
+        CurveBasis::CatmullRom => [
+            p[1],
+            p[1] + (p[2] - p[0]) / 6.0,
+            p[2] - (p[3] - p[1]) / 6.0,
+            p[2],
+        ],
+        _ => panic!("Can't convert {:?} curve segments", basis),
+    }
+}
+
+/// Convert the Bézier control points of a cubic segment to the control
+/// points of the same curve in `basis`, the inverse of `to_bezier`.
+fn from_bezier(b: [Vec4; 4], basis: CurveBasis) -> [Vec4; 4] {
+    match basis {
+        CurveBasis::Bezier => b,
+        CurveBasis::Bspline => [
+            b[0] * 6.0 - b[1] * 7.0 + b[2] * 2.0,
+            b[1] * 2.0 - b[2],
+            b[2] * 2.0 - b[1],
+            b[1] * 2.0 - b[2] * 7.0 + b[3] * 6.0,
+        ],
+        CurveBasis::CatmullRom => [
+            b[3] - (b[1] - b[0]) * 6.0,
+            b[0],
+            b[3],
+            b[0] + (b[3] - b[2]) * 6.0,
+        ],
+        _ => panic!("Can't convert {:?} curve segments", basis),
+    }
+}
+
+/// Convert the 4 control points of a cubic curve segment from the basis
+/// `from` to the basis `to`, giving the control points of the same curve
+/// in the new basis. The radius in `w` is converted along with the
+/// position. Only Bézier, B-spline and Catmull-Rom segments can be
+/// converted, panics for other bases.
+pub fn convert_segment(points: [Vec4; 4], from: CurveBasis, to: CurveBasis) -> [Vec4; 4] {
+    from_bezier(to_bezier(points, from), to)
+}
+
+/// The Catmull-Rom control points of the segment `i` of the polyline
+/// `strand`, with the end points repeated so the curve ends at them.
+fn strand_segment(strand: &[Vec4], i: usize) -> [Vec4; 4] {
+    let last = strand.len() - 1;
+    [
+        strand[i.saturating_sub(1)],
+        strand[i],
+        strand[i + 1],
+        strand[(i + 2).min(last)],
+    ]
+}
+
+/// Build a curve geometry in `basis` passing through the points of each
+/// strand, e.g. hair strands from a groom. Each strand is a polyline of
+/// at least 2 points with the radius in `w`, which is interpolated by a
+/// Catmull-Rom spline and converted to `basis`:
+///
+/// - Linear curves use the points directly, with the flag buffer filled
+///   so the segments of a strand are joined.
+/// - Catmull-Rom curves repeat the first and last point of each strand.
+/// - Bézier curves share the end points of consecutive segments.
+/// - B-spline curves get 4 control points per segment.
+/// - Hermite curves use the points with the Catmull-Rom tangents.
+///
+/// Normal oriented curves aren't supported since strands don't have
+/// normals, and cone curves are only available with the linear basis.
+pub fn from_strands<'a, S>(
+    device: &'a Device,
+    strands: &[S],
+    basis: CurveBasis,
+    curve_type: CurveType,
+) -> Result<Geometry<'a>>
+where
+    S: AsRef<[Vec4]>,
+{
+    if strands.is_empty() {
+        return Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            "from_strands needs at least one strand",
+        ));
+    }
+    if let Some(i) = strands.iter().position(|s| s.as_ref().len() < 2) {
+        return Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            &format!("strand {} must have at least 2 points", i),
+        ));
+    }
+    match curve_type {
+        CurveType::NormalOriented => {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                "normal oriented curves can't be built from strands without normals",
+            ));
+        }
+        CurveType::Cone if basis != CurveBasis::Linear => {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!("cone curves must be linear, got {:?}", basis),
+            ));
+        }
+        _ => {}
+    }
+
+    let mut verts = Vec::new();
+    let mut tangents = Vec::new();
+    let mut indices = Vec::new();
+    for strand in strands.iter().map(|s| s.as_ref()) {
+        let first = verts.len() as u32;
+        let num_segments = strand.len() as u32 - 1;
+        match basis {
+            CurveBasis::Linear => {
+                verts.extend_from_slice(strand);
+                indices.extend((0..num_segments).map(|i| first + i));
+            }
+            CurveBasis::CatmullRom => {
+                verts.push(strand[0]);
+                verts.extend_from_slice(strand);
+                verts.push(strand[strand.len() - 1]);
+                indices.extend((0..num_segments).map(|i| first + i));
+            }
+            CurveBasis::Bezier => {
+                verts.push(strand[0]);
+                for i in 0..num_segments as usize {
+                    let b = to_bezier(strand_segment(strand, i), CurveBasis::CatmullRom);
+                    verts.extend_from_slice(&b[1..]);
+                }
+                indices.extend((0..num_segments).map(|i| first + 3 * i));
+            }
+            CurveBasis::Bspline => {
+                for i in 0..num_segments as usize {
+                    let c = convert_segment(
+                        strand_segment(strand, i),
+                        CurveBasis::CatmullRom,
+                        CurveBasis::Bspline,
+                    );
+                    verts.extend_from_slice(&c);
+                }
+                indices.extend((0..num_segments).map(|i| first + 4 * i));
+            }
+            CurveBasis::Hermite => {
+                verts.extend_from_slice(strand);
+                for i in 0..strand.len() {
+                    let prev = strand[i.saturating_sub(1)];
+                    let next = strand[(i + 1).min(strand.len() - 1)];
+                    tangents.push((next - prev) * 0.5);
+                }
+                indices.extend((0..num_segments).map(|i| first + i));
+            }
+        }
+    }
+
+    let geometry = match basis {
+        CurveBasis::Linear => {
+            let mut curve = LinearCurve::try_animated(
+                device,
+                indices.len(),
+                verts.len(),
+                curve_type,
+                false,
+                1,
+            )?;
+            curve.vertex_buffer.as_mut_slice()[..verts.len()].copy_from_slice(&verts);
+            curve.index_buffer.as_mut_slice()[..indices.len()].copy_from_slice(&indices);
+            curve.compute_flags();
+            Geometry::LinearCurve(curve)
+        }
+        CurveBasis::Bezier => {
+            let mut curve = BezierCurve::try_animated(
+                device,
+                indices.len(),
+                verts.len(),
+                curve_type,
+                false,
+                1,
+            )?;
+            curve.vertex_buffer.as_mut_slice()[..verts.len()].copy_from_slice(&verts);
+            curve.index_buffer.as_mut_slice()[..indices.len()].copy_from_slice(&indices);
+            Geometry::BezierCurve(curve)
+        }
+        CurveBasis::Bspline => {
+            let mut curve = BsplineCurve::try_animated(
+                device,
+                indices.len(),
+                verts.len(),
+                curve_type,
+                false,
+                1,
+            )?;
+            curve.vertex_buffer.as_mut_slice()[..verts.len()].copy_from_slice(&verts);
+            curve.index_buffer.as_mut_slice()[..indices.len()].copy_from_slice(&indices);
+            Geometry::BsplineCurve(curve)
+        }
+        CurveBasis::CatmullRom => {
+            let mut curve = CatmullRomCurve::try_animated(
+                device,
+                indices.len(),
+                verts.len(),
+                curve_type,
+                false,
+                1,
+            )?;
+            curve.vertex_buffer.as_mut_slice()[..verts.len()].copy_from_slice(&verts);
+            curve.index_buffer.as_mut_slice()[..indices.len()].copy_from_slice(&indices);
+            Geometry::CatmullRomCurve(curve)
+        }
+        CurveBasis::Hermite => {
+            let mut curve = HermiteCurve::try_animated(
+                device,
+                indices.len(),
+                verts.len(),
+                curve_type,
+                false,
+                1,
+            )?;
+            curve.vertex_buffer.as_mut_slice()[..verts.len()].copy_from_slice(&verts);
+            curve.index_buffer.as_mut_slice()[..indices.len()].copy_from_slice(&indices);
+            curve.tangent_buffer.as_mut_slice()[..tangents.len()].copy_from_slice(&tangents);
+            Geometry::HermiteCurve(Box::new(curve))
+        }
+    };
+    Ok(geometry)
+}
+
+#[test]
+fn test_convert_segment() {
+    let p = [
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+        Vec4::new(1.0, 2.0, 0.0, 0.8),
+        Vec4::new(3.0, 2.0, 1.0, 0.5),
+        Vec4::new(4.0, 0.0, 1.0, 0.2),
+    ];
+    let bases = [
+        CurveBasis::Bezier,
+        CurveBasis::Bspline,
+        CurveBasis::CatmullRom,
+    ];
+    for &from in bases.iter() {
+        for &to in bases.iter() {
+            let back = convert_segment(convert_segment(p, from, to), to, from);
+            for i in 0..4 {
+                assert!((back[i] - p[i]).mag() < 1e-4);
+            }
+        }
+    }
+    // A Catmull-Rom segment goes through its middle control points
+    let b = convert_segment(p, CurveBasis::CatmullRom, CurveBasis::Bezier);
+    assert_eq!(b[0], p[1]);
+    assert_eq!(b[3], p[2]);
+}
+
+#[test]
+fn test_from_strands() {
+    use crate::CurveFlags;
+
+    let device = Device::new();
+    // 17 segments don't fill the buffer padding exactly
+    let strand: Vec<Vec4> = (0..18)
+        .map(|i| Vec4::new(i as f32, (i as f32).sin(), 0.0, 0.1))
+        .collect();
+    let bases = [
+        CurveBasis::Linear,
+        CurveBasis::Bezier,
+        CurveBasis::Bspline,
+        CurveBasis::CatmullRom,
+        CurveBasis::Hermite,
+    ];
+    for &basis in bases.iter() {
+        let geometry = from_strands(&device, &[&strand], basis, CurveType::Round).unwrap();
+        let (verts, indices) = match geometry {
+            Geometry::LinearCurve(ref curve) => {
+                assert_eq!(curve.flags(0), CurveFlags::NEIGHBOR_RIGHT);
+                assert_eq!(
+                    curve.flags(8),
+                    CurveFlags::NEIGHBOR_LEFT | CurveFlags::NEIGHBOR_RIGHT
+                );
+                assert_eq!(curve.flags(16), CurveFlags::NEIGHBOR_LEFT);
+                (&curve.vertex_buffer, &curve.index_buffer)
+            }
+            Geometry::BezierCurve(ref curve) => (&curve.vertex_buffer, &curve.index_buffer),
+            Geometry::BsplineCurve(ref curve) => (&curve.vertex_buffer, &curve.index_buffer),
+            Geometry::CatmullRomCurve(ref curve) => (&curve.vertex_buffer, &curve.index_buffer),
+            Geometry::HermiteCurve(ref curve) => {
+                assert_eq!(curve.tangent_buffer.len(), 18);
+                (&curve.vertex_buffer, &curve.index_buffer)
+            }
+            _ => panic!("from_strands made a {:?} curve of another type", basis),
+        };
+        let num_verts = match basis {
+            CurveBasis::Linear | CurveBasis::Hermite => 18,
+            CurveBasis::CatmullRom => 20,
+            CurveBasis::Bezier => 1 + 3 * 17,
+            CurveBasis::Bspline => 4 * 17,
+        };
+        assert_eq!(verts.len(), num_verts);
+        assert_eq!(indices.len(), 17);
+        // Each segment must start and end on the strand's points
+        for i in 0..17 {
+            let first = indices.get(i) as usize;
+            let (start, end) = match basis {
+                CurveBasis::Linear | CurveBasis::Hermite => {
+                    (verts.get(first), verts.get(first + 1))
+                }
+                _ => {
+                    let p = [
+                        verts.get(first),
+                        verts.get(first + 1),
+                        verts.get(first + 2),
+                        verts.get(first + 3),
+                    ];
+                    let b = to_bezier(p, basis);
+                    (b[0], b[3])
+                }
+            };
+            assert!((start - strand[i]).mag() < 1e-4);
+            assert!((end - strand[i + 1]).mag() < 1e-4);
+        }
+    }
+}
@@ -1,16 +1,184 @@
 #[cfg(x86_64)]
 use std::arch::x86_64;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::os::raw;
 use std::ptr;
 
+use crate::error::{self, EmbreeError, ErrorState, Result};
 use crate::sys::*;
+use crate::{DeviceProperty, Error};
+
+/// The ISAs which Embree can be restricted to with `DeviceConfig::isa`
+/// and `DeviceConfig::max_isa`, ordered from oldest to newest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Isa {
+    SSE2,
+    SSE42,
+    AVX,
+    AVX2,
+    AVX512,
+}
+
+impl Isa {
+    fn config_name(&self) -> &'static str {
+        match *self {
+            Isa::SSE2 => "sse2",
+            Isa::SSE42 => "sse4.2",
+            Isa::AVX => "avx",
+            Isa::AVX2 => "avx2",
+            Isa::AVX512 => "avx512skx",
+        }
+    }
+}
+
+/// The SIMD width Embree is allowed to use when it would lower the CPU
+/// frequency, see `DeviceConfig::frequency_level`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrequencyLevel {
+    Simd128,
+    Simd256,
+    Simd512,
+}
+
+impl FrequencyLevel {
+    fn config_name(&self) -> &'static str {
+        match *self {
+            FrequencyLevel::Simd128 => "simd128",
+            FrequencyLevel::Simd256 => "simd256",
+            FrequencyLevel::Simd512 => "simd512",
+        }
+    }
+}
+
+/// Builder for the configuration string passed to `rtcNewDevice`.
+/// Options which aren't set are left to Embree's defaults.
+///
+/// ```no_run
+/// use embree::{Device, DeviceConfig, Isa};
+///
+/// let config = DeviceConfig::new().threads(8).set_affinity(true).max_isa(Isa::AVX2);
+/// let device = Device::with_config(&config).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceConfig {
+    threads: Option<u32>,
+    set_affinity: Option<bool>,
+    isa: Option<Isa>,
+    max_isa: Option<Isa>,
+    frequency_level: Option<FrequencyLevel>,
+    hugepages: Option<bool>,
+    verbose: Option<u32>,
+}
+
+impl DeviceConfig {
+    pub fn new() -> DeviceConfig {
+        DeviceConfig::default()
+    }
+    /// Set the number of build threads Embree will use, by default
+    /// all hardware threads are used.
+    pub fn threads(mut self, threads: u32) -> DeviceConfig {
+        self.threads = Some(threads);
+        self
+    }
+    /// Pin Embree's build threads to hardware threads
+    pub fn set_affinity(mut self, affinity: bool) -> DeviceConfig {
+        self.set_affinity = Some(affinity);
+        self
+    }
+    /// Use the kernels for a specific ISA, instead of the best one supported
+    pub fn isa(mut self, isa: Isa) -> DeviceConfig {
+        self.isa = Some(isa);
+        self
+    }
+    /// Use the best kernels supported by the CPU, up to `isa`
+    pub fn max_isa(mut self, isa: Isa) -> DeviceConfig {
+        self.max_isa = Some(isa);
+        self
+    }
+    pub fn frequency_level(mut self, level: FrequencyLevel) -> DeviceConfig {
+        self.frequency_level = Some(level);
+        self
+    }
+    /// Enable or disable the use of huge pages for Embree's allocations
+    pub fn hugepages(mut self, hugepages: bool) -> DeviceConfig {
+        self.hugepages = Some(hugepages);
+        self
+    }
+    /// Set the verbosity of Embree's logging, from 0 (quiet) up to 4
+    pub fn verbose(mut self, verbose: u32) -> DeviceConfig {
+        self.verbose = Some(verbose);
+        self
+    }
+    /// Validate the options and build the Embree configuration string
+    pub fn to_config_string(&self) -> Result<String> {
+        if let (Some(isa), Some(max_isa)) = (self.isa, self.max_isa) {
+            if isa > max_isa {
+                return Err(EmbreeError::new(
+                    Error::INVALID_ARGUMENT,
+                    &format!("isa {:?} is above max_isa {:?}", isa, max_isa),
+                ));
+            }
+        }
+        if let Some(verbose) = self.verbose {
+            if verbose > 4 {
+                return Err(EmbreeError::new(
+                    Error::INVALID_ARGUMENT,
+                    &format!("verbose level {} must be between 0 and 4", verbose),
+                ));
+            }
+        }
+        let mut options = Vec::new();
+        if let Some(threads) = self.threads {
+            options.push(format!("threads={}", threads));
+        }
+        if let Some(affinity) = self.set_affinity {
+            options.push(format!("set_affinity={}", affinity as u32));
+        }
+        if let Some(isa) = self.isa {
+            options.push(format!("isa={}", isa.config_name()));
+        }
+        if let Some(isa) = self.max_isa {
+            options.push(format!("max_isa={}", isa.config_name()));
+        }
+        if let Some(level) = self.frequency_level {
+            options.push(format!("frequency_level={}", level.config_name()));
+        }
+        if let Some(hugepages) = self.hugepages {
+            options.push(format!("hugepages={}", hugepages as u32));
+        }
+        if let Some(verbose) = self.verbose {
+            options.push(format!("verbose={}", verbose));
+        }
+        Ok(options.join(","))
+    }
+}
 
 pub struct Device {
     pub(crate) handle: RTCDevice,
+    /// The error state passed to Embree's error callback
+    errors: Box<ErrorState>,
 }
 
 impl Device {
+    /// Create a new device with the default configuration, panics
+    /// if the device can't be created. See `try_new` for a fallible version.
     pub fn new() -> Device {
+        Device::try_new().expect("Failed to create Embree device")
+    }
+    /// Create a new device with the default configuration.
+    pub fn try_new() -> Result<Device> {
+        Device::create(None)
+    }
+    pub fn debug() -> Device {
+        Device::with_config(&DeviceConfig::new().verbose(4))
+            .expect("Failed to create Embree device")
+    }
+    /// Create a new device with the configuration `config`
+    pub fn with_config(config: &DeviceConfig) -> Result<Device> {
+        let cfg = CString::new(config.to_config_string()?).unwrap();
+        Device::create(Some(&cfg))
+    }
+    fn create(config: Option<&CStr>) -> Result<Device> {
         // Set the flush zero and denormals modes from Embrees's perf. recommendations
         // https://embree.github.io/api.html#performance-recommendations
         // Though, in Rust I think we just call the below function to do both
@@ -19,16 +187,93 @@ impl Device {
             x86_64::_MM_SET_FLUSH_ZERO_MODE(x86_64::_MM_FLUSH_ZERO_ON);
         }
 
-        Device {
-            handle: unsafe { rtcNewDevice(ptr::null()) },
+        let handle = unsafe { rtcNewDevice(config.map_or(ptr::null(), |c| c.as_ptr())) };
+        if handle.is_null() {
+            // Errors creating the device are reported on the null device
+            let code = unsafe { rtcGetDeviceError(ptr::null_mut()) };
+            return Err(EmbreeError::new(code, "Failed to create device"));
         }
+        let errors = Box::new(ErrorState::new());
+        unsafe {
+            rtcSetDeviceErrorFunction(
+                handle,
+                Some(error::error_function),
+                &*errors as *const ErrorState as *mut raw::c_void,
+            );
+        }
+        Ok(Device { handle, errors })
     }
-    pub fn debug() -> Device {
-        let cfg = CString::new("verbose=4").unwrap();
-        Device {
-            handle: unsafe { rtcNewDevice(cfg.as_ptr()) },
+    /// Set a callback to be called with each error reported by Embree
+    /// on this device, e.g. to log Embree's diagnostics. The callback
+    /// may be called from any thread using the device.
+    pub fn set_error_function<F>(&mut self, f: F)
+    where
+        F: Fn(&EmbreeError) + Send + Sync + 'static,
+    {
+        self.errors.set_callback(Some(Box::new(f)));
+    }
+    /// Get and clear the error stored for the current thread, returning
+    /// `Ok` if no error has occurred since it was last checked.
+    pub fn get_error(&self) -> Result<()> {
+        let code = unsafe { rtcGetDeviceError(self.handle) };
+        if code == Error::NONE {
+            Ok(())
+        } else {
+            Err(self.errors.take_error(code))
+        }
+    }
+    /// Clear the error stored for the current thread, so errors left by
+    /// earlier calls aren't reported as errors of the next one
+    pub(crate) fn clear_error(&self) {
+        let _ = self.get_error();
+    }
+    /// Get the error stored for the current thread after a call which
+    /// returned a null handle, or an unknown error with `message` if
+    /// Embree didn't store one
+    pub(crate) fn error_or(&self, message: &str) -> EmbreeError {
+        match self.get_error() {
+            Err(e) => e,
+            Ok(()) => EmbreeError::new(Error::UNKNOWN, message),
         }
     }
+    /// Query a property of the device, see the Embree documentation
+    /// for `rtcGetDeviceProperty` for the meaning of each value.
+    pub fn get_property(&self, prop: DeviceProperty) -> isize {
+        unsafe { rtcGetDeviceProperty(self.handle, prop) }
+    }
+    /// Get the (major, minor, patch) version of Embree
+    pub fn version(&self) -> (u32, u32, u32) {
+        (
+            self.get_property(DeviceProperty::VERSION_MAJOR) as u32,
+            self.get_property(DeviceProperty::VERSION_MINOR) as u32,
+            self.get_property(DeviceProperty::VERSION_PATCH) as u32,
+        )
+    }
+    /// Get the widest ray packet size natively supported by the ISA
+    /// Embree selected, or 1 if no packets are natively supported.
+    pub fn native_packet_width(&self) -> usize {
+        if self.get_property(DeviceProperty::NATIVE_RAY16_SUPPORTED) != 0 {
+            16
+        } else if self.get_property(DeviceProperty::NATIVE_RAY8_SUPPORTED) != 0 {
+            8
+        } else if self.get_property(DeviceProperty::NATIVE_RAY4_SUPPORTED) != 0 {
+            4
+        } else {
+            1
+        }
+    }
+    /// Check if Embree was built with support for ray masks
+    pub fn ray_mask_supported(&self) -> bool {
+        self.get_property(DeviceProperty::RAY_MASK_SUPPORTED) != 0
+    }
+    /// Check if Embree was built with backface culling enabled
+    pub fn backface_culling_enabled(&self) -> bool {
+        self.get_property(DeviceProperty::BACKFACE_CULLING_ENABLED) != 0
+    }
+    /// Check if Embree was built with support for filter functions
+    pub fn filter_function_supported(&self) -> bool {
+        self.get_property(DeviceProperty::FILTER_FUNCTION_SUPPORTED) != 0
+    }
     // TODO: Setup the flush zero and denormals mode needed by Embree
     // using the Rust SIMD when it's in core
 }
@@ -42,3 +287,26 @@ impl Drop for Device {
 }
 
 unsafe impl Sync for Device {}
+
+#[test]
+fn test_device_config_string() {
+    let config = DeviceConfig::new()
+        .threads(8)
+        .set_affinity(true)
+        .isa(Isa::AVX)
+        .max_isa(Isa::AVX512)
+        .frequency_level(FrequencyLevel::Simd256)
+        .hugepages(false)
+        .verbose(1);
+    assert_eq!(
+        config.to_config_string().unwrap(),
+        "threads=8,set_affinity=1,isa=avx,max_isa=avx512skx,\
+         frequency_level=simd256,hugepages=0,verbose=1"
+    );
+    assert_eq!(DeviceConfig::new().to_config_string().unwrap(), "");
+    assert!(DeviceConfig::new()
+        .isa(Isa::AVX2)
+        .max_isa(Isa::SSE42)
+        .to_config_string()
+        .is_err());
+}
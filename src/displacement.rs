@@ -0,0 +1,113 @@
+use std::slice;
+
+use ultraviolet::Vec3;
+
+use crate::catch_unwind_abort;
+use crate::geometry::GeometryData;
+use crate::sys::*;
+
+/// A displacement callback for subdivision surfaces, which is called with
+/// batches of vertices of the tessellated surface to displace. Embree
+/// doesn't enforce the displacement bound, after the callback returns the
+/// displaced positions are clamped to it by this crate.
+pub type DisplacementFn<'a> = dyn Fn(&mut DisplacementArgs) + Sync + 'a;
+
+/// The most vertices passed to the callback at once, so the original
+/// positions can be kept on the stack for clamping. Larger batches from
+/// Embree are split into several calls.
+const MAX_BATCH: usize = 64;
+
+/// The arguments passed to displacement callbacks, wrapping
+/// `RTCDisplacementFunctionNArguments`. The vertices are passed in SoA
+/// layout, vertex `i` is at `u[i], v[i]` on the patch with the surface
+/// normal `ng_x[i], ng_y[i], ng_z[i]` and the position `p_x[i], p_y[i],
+/// p_z[i]`, which the callback displaces.
+pub struct DisplacementArgs<'a> {
+    pub prim_id: u32,
+    pub time_step: u32,
+    pub u: &'a [f32],
+    pub v: &'a [f32],
+    pub ng_x: &'a [f32],
+    pub ng_y: &'a [f32],
+    pub ng_z: &'a [f32],
+    pub p_x: &'a mut [f32],
+    pub p_y: &'a mut [f32],
+    pub p_z: &'a mut [f32],
+}
+
+impl<'a> DisplacementArgs<'a> {
+    /// The number of vertices to displace
+    pub fn len(&self) -> usize {
+        self.u.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.u.is_empty()
+    }
+    /// Get the surface normal of vertex `i`, which is not normalized
+    pub fn normal(&self, i: usize) -> Vec3 {
+        Vec3::new(self.ng_x[i], self.ng_y[i], self.ng_z[i])
+    }
+    pub fn position(&self, i: usize) -> Vec3 {
+        Vec3::new(self.p_x[i], self.p_y[i], self.p_z[i])
+    }
+    pub fn set_position(&mut self, i: usize, p: Vec3) {
+        self.p_x[i] = p.x;
+        self.p_y[i] = p.y;
+        self.p_z[i] = p.z;
+    }
+    /// Move vertex `i` by `distance` along its normalized surface normal
+    pub fn displace_along_normal(&mut self, i: usize, distance: f32) {
+        let p = self.position(i) + self.normal(i).normalized() * distance;
+        self.set_position(i, p);
+    }
+}
+
+/// Clamp each displaced position in `args` to be within `bound` of its
+/// original position in `orig`
+fn clamp_displacement(args: &mut DisplacementArgs, orig: &[Vec3], bound: f32) {
+    for (i, &o) in orig.iter().enumerate() {
+        let d = args.position(i) - o;
+        let len = d.mag();
+        if len > bound {
+            args.set_position(i, o + d * (bound / len));
+        }
+    }
+}
+
+pub(crate) unsafe extern "C" fn displacement_function(
+    args: *const RTCDisplacementFunctionNArguments,
+) {
+    let args = &*args;
+    let data = &*(args.geometryUserPtr as *const GeometryData);
+    let displacement = match data.displacement {
+        Some(ref d) => d,
+        None => return,
+    };
+    let n = args.N as usize;
+    let mut orig = [Vec3::zero(); MAX_BATCH];
+    for start in (0..n).step_by(MAX_BATCH) {
+        let len = (n - start).min(MAX_BATCH);
+        for (i, o) in orig[..len].iter_mut().enumerate() {
+            let j = start + i;
+            *o = Vec3::new(*args.P_x.add(j), *args.P_y.add(j), *args.P_z.add(j));
+        }
+        let mut displacement_args = DisplacementArgs {
+            prim_id: args.primID,
+            time_step: args.timeStep,
+            u: slice::from_raw_parts(args.u.add(start), len),
+            v: slice::from_raw_parts(args.v.add(start), len),
+            ng_x: slice::from_raw_parts(args.Ng_x.add(start), len),
+            ng_y: slice::from_raw_parts(args.Ng_y.add(start), len),
+            ng_z: slice::from_raw_parts(args.Ng_z.add(start), len),
+            p_x: slice::from_raw_parts_mut(args.P_x.add(start), len),
+            p_y: slice::from_raw_parts_mut(args.P_y.add(start), len),
+            p_z: slice::from_raw_parts_mut(args.P_z.add(start), len),
+        };
+        catch_unwind_abort(|| displacement(&mut displacement_args));
+        clamp_displacement(
+            &mut displacement_args,
+            &orig[..len],
+            data.displacement_bound,
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw;
+use std::sync::Mutex;
+
+use crate::sys::*;
+use crate::Error;
+
+/// An error reported by Embree, with the error code and the message
+/// passed to the device's error callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbreeError {
+    pub code: Error,
+    pub message: String,
+}
+
+pub type Result<T> = std::result::Result<T, EmbreeError>;
+
+impl EmbreeError {
+    pub fn new(code: Error, message: &str) -> EmbreeError {
+        EmbreeError {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for EmbreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "Embree error {:?}", self.code)
+        } else {
+            write!(f, "Embree error {:?}: {}", self.code, self.message)
+        }
+    }
+}
+
+impl std::error::Error for EmbreeError {}
+
+pub type ErrorFn = dyn Fn(&EmbreeError) + Send + Sync;
+
+thread_local! {
+    /// The last error reported to the callback on this thread for each
+    /// device, keyed by the address of its `ErrorState`. Like the code
+    /// returned by `rtcGetDeviceError`, the error is stored per thread so
+    /// one thread can't take the message of another thread's error.
+    static LAST_ERROR: RefCell<HashMap<usize, EmbreeError>> = RefCell::new(HashMap::new());
+}
+
+/// The error state of a device, passed as the user pointer of the
+/// device's error callback.
+pub(crate) struct ErrorState {
+    callback: Mutex<Option<Box<ErrorFn>>>,
+}
+
+impl ErrorState {
+    pub(crate) fn new() -> ErrorState {
+        ErrorState {
+            callback: Mutex::new(None),
+        }
+    }
+    pub(crate) fn set_callback(&self, callback: Option<Box<ErrorFn>>) {
+        *self.callback.lock().unwrap() = callback;
+    }
+    fn key(&self) -> usize {
+        self as *const ErrorState as usize
+    }
+    fn set_last_error(&self, error: EmbreeError) {
+        LAST_ERROR.with(|errors| errors.borrow_mut().insert(self.key(), error));
+    }
+    /// Get the error for `code` returned by `rtcGetDeviceError` on the
+    /// current thread, with the message from the callback if we have it.
+    pub(crate) fn take_error(&self, code: Error) -> EmbreeError {
+        match LAST_ERROR.with(|errors| errors.borrow_mut().remove(&self.key())) {
+            Some(e) if e.code == code => e,
+            _ => EmbreeError::new(code, ""),
+        }
+    }
+}
+
+impl Drop for ErrorState {
+    fn drop(&mut self) {
+        // Errors left on other threads are only kept until a device
+        // reusing this address takes or replaces them
+        let key = self.key();
+        let _ = LAST_ERROR.try_with(|errors| errors.borrow_mut().remove(&key));
+    }
+}
+
+pub(crate) unsafe extern "C" fn error_function(
+    user_ptr: *mut raw::c_void,
+    code: RTCError,
+    message: *const raw::c_char,
+) {
+    let state = &*(user_ptr as *const ErrorState);
+    let message = if message.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    };
+    let error = EmbreeError { code, message };
+    crate::catch_unwind_abort(|| {
+        if let Some(ref callback) = *state.callback.lock().unwrap() {
+            callback(&error);
+        }
+    });
+    state.set_last_error(error);
+}
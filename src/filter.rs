@@ -0,0 +1,118 @@
+use std::ops::{Deref, DerefMut};
+use std::slice;
+
+use crate::catch_unwind_abort;
+use crate::geometry::GeometryData;
+use crate::ray::IntersectContext;
+use crate::ray_stream::{HitNRef, RayNRef};
+use crate::sys::*;
+
+/// A filter callback which is run for each potential hit found during
+/// traversal, and can reject hits with `FilterArgs::reject`.
+pub type FilterFn<'a> = dyn Fn(&mut FilterArgs) + Sync + 'a;
+
+/// The arguments passed to intersection and occlusion filter callbacks,
+/// wrapping `RTCFilterFunctionNArguments`. The ray and hit are views of
+/// the SoA packet of rays being traced, only the rays marked valid
+/// have a potential hit to be filtered.
+pub struct FilterArgs<'a> {
+    valid: &'a mut [i32],
+    context: &'a IntersectContext,
+    pub ray: RayNRef<'a>,
+    pub hit: HitNRef<'a>,
+}
+
+impl<'a> FilterArgs<'a> {
+    /// The number of rays in the packet, including invalid ones
+    pub fn len(&self) -> usize {
+        self.valid.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.valid.is_empty()
+    }
+    /// Check if ray `i` has a potential hit to be filtered
+    pub fn valid(&self, i: usize) -> bool {
+        self.valid[i] != 0
+    }
+    /// Reject the potential hit of ray `i`, traversal will continue
+    /// looking for other hits along the ray.
+    pub fn reject(&mut self, i: usize) {
+        self.valid[i] = 0;
+    }
+    pub fn context(&self) -> &IntersectContext {
+        self.context
+    }
+}
+
+/// An intersection context with a filter callback which is run on
+/// every potential hit with any geometry in the scene, after the
+/// geometry's own filters. The scene must have the
+/// `SceneFlags::CONTEXT_FILTER_FUNCTION` flag set to use the context
+/// filter, see `Scene::set_flags`. The `FilterContext` derefs to an
+/// `IntersectContext` and can be passed anywhere one is used.
+#[repr(C)]
+pub struct FilterContext<'a> {
+    ctx: IntersectContext,
+    filter: &'a FilterFn<'a>,
+}
+
+impl<'a> FilterContext<'a> {
+    pub fn coherent(filter: &'a FilterFn<'a>) -> FilterContext<'a> {
+        FilterContext::new(IntersectContext::coherent(), filter)
+    }
+    pub fn incoherent(filter: &'a FilterFn<'a>) -> FilterContext<'a> {
+        FilterContext::new(IntersectContext::incoherent(), filter)
+    }
+    fn new(mut ctx: IntersectContext, filter: &'a FilterFn<'a>) -> FilterContext<'a> {
+        ctx.filter = Some(context_filter_function);
+        FilterContext { ctx, filter }
+    }
+}
+
+impl<'a> Deref for FilterContext<'a> {
+    type Target = IntersectContext;
+    fn deref(&self) -> &IntersectContext {
+        &self.ctx
+    }
+}
+
+impl<'a> DerefMut for FilterContext<'a> {
+    fn deref_mut(&mut self) -> &mut IntersectContext {
+        &mut self.ctx
+    }
+}
+
+unsafe fn run_filter(args: *const RTCFilterFunctionNArguments, filter: &FilterFn) {
+    let args = &*args;
+    let n = args.N as usize;
+    let mut filter_args = FilterArgs {
+        valid: slice::from_raw_parts_mut(args.valid, n),
+        context: &*args.context,
+        ray: RayNRef::from_raw(args.ray, n),
+        hit: HitNRef::from_raw(args.hit, n),
+    };
+    catch_unwind_abort(|| filter(&mut filter_args));
+}
+
+pub(crate) unsafe extern "C" fn intersect_filter_function(
+    args: *const RTCFilterFunctionNArguments,
+) {
+    let data = &*((*args).geometryUserPtr as *const GeometryData);
+    if let Some(ref filter) = data.intersect_filter {
+        run_filter(args, &**filter);
+    }
+}
+
+pub(crate) unsafe extern "C" fn occluded_filter_function(args: *const RTCFilterFunctionNArguments) {
+    let data = &*((*args).geometryUserPtr as *const GeometryData);
+    if let Some(ref filter) = data.occluded_filter {
+        run_filter(args, &**filter);
+    }
+}
+
+unsafe extern "C" fn context_filter_function(args: *const RTCFilterFunctionNArguments) {
+    // The IntersectContext is the first member of the FilterContext,
+    // so we can get back the filter context from it.
+    let ctx = &*((*args).context as *const FilterContext);
+    run_filter(args, ctx.filter);
+}
@@ -1,25 +1,45 @@
+use std::os::raw;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Weak;
+use std::{mem, ptr};
+
+use crate::buffer::{self, Buffer, BufferState};
+use crate::device::Device;
+use crate::displacement::DisplacementFn;
+use crate::error::{EmbreeError, Result};
+use crate::filter::{self, FilterArgs, FilterFn};
 use crate::sys::*;
+use crate::vertex_attribute::{self, Interpolated, VertexAttribute};
+use crate::{BufferType, BuildQuality, Error, Format, GeometryType};
 
 use crate::bezier_curve;
 use crate::bspline_curve;
 use crate::catmull_rom_curve;
+use crate::grid_mesh;
 use crate::hermite_curve;
 use crate::instance;
 use crate::linear_curve;
+use crate::points;
 use crate::quad_mesh;
 use crate::subdiv_mesh;
 use crate::triangle_mesh;
+use crate::user_geometry;
 
+/// A geometry of any type, which can be attached to a `Scene`. The
+/// larger geometry types are boxed to keep the enum small.
 pub enum Geometry<'a> {
     Triangle(triangle_mesh::TriangleMesh<'a>),
     Quad(quad_mesh::QuadMesh<'a>),
-    Subdiv(subdiv_mesh::SubdivMesh<'a>),
+    Subdiv(Box<subdiv_mesh::SubdivMesh<'a>>),
+    Grid(grid_mesh::GridMesh<'a>),
     Instance(instance::Instance<'a>),
     LinearCurve(linear_curve::LinearCurve<'a>),
     BsplineCurve(bspline_curve::BsplineCurve<'a>),
     BezierCurve(bezier_curve::BezierCurve<'a>),
-    HermiteCurve(hermite_curve::HermiteCurve<'a>),
+    HermiteCurve(Box<hermite_curve::HermiteCurve<'a>>),
     CatmullRomCurve(catmull_rom_curve::CatmullRomCurve<'a>),
+    Points(points::Points<'a>),
+    User(user_geometry::UserGeometry<'a>),
 }
 
 /// Geometry trait implemented by all Embree Geometry types
@@ -29,25 +49,433 @@ impl<'a> Geometry<'a> {
             &Geometry::Triangle(ref m) => m.handle,
             &Geometry::Quad(ref q) => q.handle,
             &Geometry::Subdiv(ref s) => s.handle,
+            Geometry::Grid(ref g) => g.handle,
             &Geometry::Instance(ref i) => i.handle,
             &Geometry::LinearCurve(ref lc) => lc.handle,
             &Geometry::BsplineCurve(ref bsc) => bsc.handle,
             &Geometry::BezierCurve(ref bzc) => bzc.handle,
             &Geometry::HermiteCurve(ref hc) => hc.handle,
             &Geometry::CatmullRomCurve(ref crc) => crc.handle,
+            Geometry::Points(ref p) => p.handle,
+            Geometry::User(ref u) => u.handle,
+        }
+    }
+    /// Get the device the geometry was created on
+    pub fn device(&self) -> &'a Device {
+        match self {
+            Geometry::Triangle(ref m) => m.device,
+            Geometry::Quad(ref q) => q.device,
+            Geometry::Subdiv(ref s) => s.device,
+            Geometry::Grid(ref g) => g.device,
+            Geometry::Instance(ref i) => i.device,
+            Geometry::LinearCurve(ref lc) => lc.device,
+            Geometry::BsplineCurve(ref bsc) => bsc.device,
+            Geometry::BezierCurve(ref bzc) => bzc.device,
+            Geometry::HermiteCurve(ref hc) => hc.device,
+            Geometry::CatmullRomCurve(ref crc) => crc.device,
+            Geometry::Points(ref p) => p.device,
+            Geometry::User(ref u) => u.device,
         }
     }
+    /// Commit the geometry, sending any changes made to its buffers
+    /// since it was last committed to Embree.
     pub fn commit(&mut self) {
+        self.update_buffers();
+        unsafe {
+            // Committing now covers any pending changes
+            take_changed(self.handle());
+            commit_geometry(self.handle());
+        }
+    }
+    /// Commit the geometry, returning an error if Embree reported one
+    /// while setting up or committing it.
+    pub fn try_commit(&mut self) -> Result<()> {
+        self.commit();
+        self.device().get_error()
+    }
+    /// Set the time range the time steps of a motion blurred geometry
+    /// span, within the [0, 1] time range of the ray. By default the time
+    /// steps are spread over the whole range.
+    pub fn set_time_range(&mut self, start: f32, end: f32) {
+        unsafe {
+            rtcSetGeometryTimeRange(self.handle(), start, end);
+            mark_changed(self.handle());
+        }
+    }
+    /// Attach `buffer` to `slot` of the geometry's `buf_type` buffers with
+    /// `format`, e.g. to share one vertex buffer between several meshes.
+    /// This replaces the buffer the geometry was created with for the slot.
+    /// The buffer keeps track of each geometry it's attached to, writing
+    /// to it through `Buffer::map_mut` updates all of them.
+    pub fn set_buffer<T>(
+        &mut self,
+        buffer: &mut Buffer<'a, T>,
+        buf_type: BufferType,
+        slot: u32,
+        format: Format,
+    ) -> Result<()> {
+        let handle = self.handle();
+        unsafe {
+            rtcSetGeometryBuffer(
+                handle,
+                buf_type,
+                slot,
+                format,
+                buffer.handle,
+                buffer.offset(),
+                buffer.stride(),
+                buffer.len(),
+            );
+        }
+        self.device().get_error()?;
+        buffer.set_attachment(handle, buf_type, slot);
+        unsafe {
+            mark_changed(handle);
+        }
+        Ok(())
+    }
+    /// Attach `count` elements of the user memory `data` to `slot` of the
+    /// geometry's `buf_type` buffers without copying it, with the elements
+    /// `byte_stride` bytes apart starting at the start of `data`. This
+    /// allows using interleaved or flat arrays, e.g. the `f32`
+    /// positions of a loaded mesh as `Format::FLOAT3` vertices. The data is
+    /// borrowed for the lifetime of the geometry.
+    ///
+    /// The data and stride must be aligned to the size of the components
+    /// of `format`, and for float and int formats 16 bytes must be readable
+    /// from the start of the last element, e.g. flat `FLOAT3`
+    /// positions need one extra float of padding. Changes to the data
+    /// aren't tracked, unlike buffers attached with `set_buffer`.
+    pub fn set_shared_buffer<T>(
+        &mut self,
+        data: &'a [T],
+        buf_type: BufferType,
+        slot: u32,
+        format: Format,
+        byte_stride: usize,
+        count: usize,
+    ) -> Result<()> {
+        buffer::check_layout(
+            data.as_ptr() as usize,
+            mem::size_of_val(data),
+            0,
+            byte_stride,
+            buffer::format_alignment(format),
+            buffer::format_read_size(format, mem::size_of::<T>()),
+            count,
+        )?;
+        unsafe {
+            rtcSetSharedGeometryBuffer(
+                self.handle(),
+                buf_type,
+                slot,
+                format,
+                data.as_ptr() as *const raw::c_void,
+                0,
+                byte_stride,
+                count,
+            );
+            mark_changed(self.handle());
+        }
+        self.device().get_error()
+    }
+    /// Add a vertex attribute buffer to the geometry with `components`
+    /// floats per vertex and return its slot. The attribute can be filled
+    /// through `vertex_attributes_mut` and interpolated at hits with
+    /// `interpolate` once the geometry is committed.
+    pub fn add_vertex_attribute(&mut self, components: u32) -> Result<u32> {
+        let device = self.device();
+        let handle = self.handle();
+        let (attributes, num_verts) = match self {
+            Geometry::Triangle(ref mut m) => (&mut m.vertex_attributes, m.vertex_buffer.len()),
+            Geometry::Quad(ref mut q) => (&mut q.vertex_attributes, q.vertex_buffer.len()),
+            Geometry::Subdiv(ref mut s) => (&mut s.vertex_attributes, s.vertex_buffer.len()),
+            Geometry::Grid(ref mut g) => (&mut g.vertex_attributes, g.vertex_buffer.len()),
+            Geometry::LinearCurve(ref mut lc) => {
+                (&mut lc.vertex_attributes, lc.vertex_buffer.len())
+            }
+            Geometry::BsplineCurve(ref mut bsc) => {
+                (&mut bsc.vertex_attributes, bsc.vertex_buffer.len())
+            }
+            Geometry::BezierCurve(ref mut bzc) => {
+                (&mut bzc.vertex_attributes, bzc.vertex_buffer.len())
+            }
+            Geometry::HermiteCurve(ref mut hc) => {
+                (&mut hc.vertex_attributes, hc.vertex_buffer.len())
+            }
+            Geometry::CatmullRomCurve(ref mut crc) => {
+                (&mut crc.vertex_attributes, crc.vertex_buffer.len())
+            }
+            Geometry::Points(ref mut p) => (&mut p.vertex_attributes, p.vertex_buffer.len()),
+            _ => {
+                return Err(EmbreeError::new(
+                    Error::INVALID_OPERATION,
+                    "geometry type does not support vertex attributes",
+                ))
+            }
+        };
+        let slot = vertex_attribute::add_vertex_attribute(
+            device, handle, attributes, num_verts, components,
+        )?;
+        unsafe {
+            mark_changed(handle);
+        }
+        Ok(slot)
+    }
+    pub fn vertex_attributes(&self) -> &[VertexAttribute<'a>] {
+        match self {
+            Geometry::Triangle(ref m) => &m.vertex_attributes,
+            Geometry::Quad(ref q) => &q.vertex_attributes,
+            Geometry::Subdiv(ref s) => &s.vertex_attributes,
+            Geometry::Grid(ref g) => &g.vertex_attributes,
+            Geometry::LinearCurve(ref lc) => &lc.vertex_attributes,
+            Geometry::BsplineCurve(ref bsc) => &bsc.vertex_attributes,
+            Geometry::BezierCurve(ref bzc) => &bzc.vertex_attributes,
+            Geometry::HermiteCurve(ref hc) => &hc.vertex_attributes,
+            Geometry::CatmullRomCurve(ref crc) => &crc.vertex_attributes,
+            Geometry::Points(ref p) => &p.vertex_attributes,
+            _ => &[],
+        }
+    }
+    pub fn vertex_attributes_mut(&mut self) -> &mut [VertexAttribute<'a>] {
+        match self {
+            Geometry::Triangle(ref mut m) => &mut m.vertex_attributes,
+            Geometry::Quad(ref mut q) => &mut q.vertex_attributes,
+            Geometry::Subdiv(ref mut s) => &mut s.vertex_attributes,
+            Geometry::Grid(ref mut g) => &mut g.vertex_attributes,
+            Geometry::LinearCurve(ref mut lc) => &mut lc.vertex_attributes,
+            Geometry::BsplineCurve(ref mut bsc) => &mut bsc.vertex_attributes,
+            Geometry::BezierCurve(ref mut bzc) => &mut bzc.vertex_attributes,
+            Geometry::HermiteCurve(ref mut hc) => &mut hc.vertex_attributes,
+            Geometry::CatmullRomCurve(ref mut crc) => &mut crc.vertex_attributes,
+            Geometry::Points(ref mut p) => &mut p.vertex_attributes,
+            _ => &mut [],
+        }
+    }
+    /// Interpolate the vertex attribute `slot` at the hit `u, v` on the
+    /// primitive `prim_id`, e.g. to get UVs or smooth shading normals.
+    /// For subdivision surfaces this evaluates the limit surface. Returns
+    /// an error if the geometry has no attribute `slot` or primitive
+    /// `prim_id`, or hasn't been committed.
+    pub fn interpolate(&self, prim_id: u32, u: f32, v: f32, slot: u32) -> Result<Interpolated> {
+        let components = self.attribute_components(slot)?;
+        self.check_prim_id(prim_id)?;
+        Ok(vertex_attribute::interpolate(
+            self.handle(),
+            prim_id,
+            u,
+            v,
+            BufferType::VERTEX_ATTRIBUTE,
+            slot,
+            components,
+        ))
+    }
+    /// Interpolate the vertex attribute `slot` for a batch of hits
+    /// on the primitives `prim_ids` at `u, v`.
+    pub fn interpolate_n(
+        &self,
+        prim_ids: &[u32],
+        u: &[f32],
+        v: &[f32],
+        slot: u32,
+    ) -> Result<Vec<Interpolated>> {
+        let components = self.attribute_components(slot)?;
+        if u.len() != prim_ids.len() || v.len() != prim_ids.len() {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!(
+                    "interpolate_n needs u and v for each of the {} primitives, got {} and {}",
+                    prim_ids.len(),
+                    u.len(),
+                    v.len()
+                ),
+            ));
+        }
+        for &prim_id in prim_ids {
+            self.check_prim_id(prim_id)?;
+        }
+        Ok(vertex_attribute::interpolate_n(
+            self.handle(),
+            prim_ids,
+            u,
+            v,
+            BufferType::VERTEX_ATTRIBUTE,
+            slot,
+            components,
+        ))
+    }
+    /// Interpolate the vertex positions at the hit `u, v` on the primitive
+    /// `prim_id`. For subdivision surfaces the cross product of the
+    /// derivatives gives the smooth limit surface normal.
+    pub fn interpolate_vertex(&self, prim_id: u32, u: f32, v: f32) -> Result<Interpolated> {
+        self.check_interpolate()?;
+        self.check_prim_id(prim_id)?;
+        Ok(vertex_attribute::interpolate(
+            self.handle(),
+            prim_id,
+            u,
+            v,
+            BufferType::VERTEX,
+            0,
+            3,
+        ))
+    }
+    /// Check the geometry can be interpolated, it must have vertices and
+    /// have been committed
+    fn check_interpolate(&self) -> Result<()> {
+        match self {
+            Geometry::Instance(_) | Geometry::User(_) => {
+                return Err(EmbreeError::new(
+                    Error::INVALID_ARGUMENT,
+                    "geometry type does not support interpolation",
+                ));
+            }
+            _ => {}
+        }
+        if unsafe { !is_committed(self.handle()) } {
+            return Err(EmbreeError::new(
+                Error::INVALID_OPERATION,
+                "geometry must be committed before interpolating",
+            ));
+        }
+        Ok(())
+    }
+    /// Check `prim_id` is one of the geometry's primitives, so Embree
+    /// doesn't read past the end of its buffers
+    fn check_prim_id(&self, prim_id: u32) -> Result<()> {
+        let num_prims = match *self {
+            Geometry::Triangle(ref m) => m.index_buffer.len(),
+            Geometry::Quad(ref q) => q.index_buffer.len(),
+            Geometry::Subdiv(ref s) => s.face_buffer.len(),
+            Geometry::Grid(ref g) => g.index_buffer.len(),
+            Geometry::LinearCurve(ref lc) => lc.index_buffer.len(),
+            Geometry::BsplineCurve(ref bsc) => bsc.index_buffer.len(),
+            Geometry::BezierCurve(ref bzc) => bzc.index_buffer.len(),
+            Geometry::HermiteCurve(ref hc) => hc.index_buffer.len(),
+            Geometry::CatmullRomCurve(ref crc) => crc.index_buffer.len(),
+            Geometry::Points(ref p) => p.vertex_buffer.len(),
+            Geometry::Instance(_) | Geometry::User(_) => 0,
+        };
+        if prim_id as usize >= num_prims {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!("no primitive {}, the geometry has {}", prim_id, num_prims),
+            ));
+        }
+        Ok(())
+    }
+    /// Get the number of components of the vertex attribute `slot`,
+    /// checking it can be interpolated
+    fn attribute_components(&self, slot: u32) -> Result<u32> {
+        self.check_interpolate()?;
+        match self.vertex_attributes().get(slot as usize) {
+            Some(attribute) => Ok(attribute.components()),
+            None => Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!(
+                    "no vertex attribute in slot {}, the geometry has {}",
+                    slot,
+                    self.vertex_attributes().len()
+                ),
+            )),
+        }
+    }
+    /// Set a filter callback to run on each potential hit found with this
+    /// geometry by `intersect` queries. The filter can reject hits, e.g. to
+    /// implement alpha testing. The geometry must be committed after
+    /// setting the filter.
+    pub fn set_intersect_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&mut FilterArgs) + Sync + 'a,
+    {
         unsafe {
-            rtcCommitGeometry(self.handle());
+            self.data().intersect_filter = Some(Box::new(filter));
+            rtcSetGeometryIntersectFilterFunction(
+                self.handle(),
+                Some(filter::intersect_filter_function),
+            );
+        }
+    }
+    /// Set a filter callback to run on each potential hit found with this
+    /// geometry by `occluded` queries, e.g. to implement transparent
+    /// shadows. The geometry must be committed after setting the filter.
+    pub fn set_occluded_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&mut FilterArgs) + Sync + 'a,
+    {
+        unsafe {
+            self.data().occluded_filter = Some(Box::new(filter));
+            rtcSetGeometryOccludedFilterFunction(
+                self.handle(),
+                Some(filter::occluded_filter_function),
+            );
+        }
+    }
+    /// Set the mask of the geometry, rays only hit it if their `mask`
+    /// and the geometry's mask have a bit in common. Requires the
+    /// device to be built with `EMBREE_RAY_MASK` to have an effect.
+    pub fn set_mask(&mut self, mask: u32) {
+        unsafe { set_mask(self.handle(), mask) }
+    }
+    /// Get the mask of the geometry, defaults to all bits set
+    pub fn mask(&self) -> u32 {
+        unsafe { state(self.handle()).mask }
+    }
+    /// Enable the geometry, the scene has to be committed again for
+    /// the change to take effect.
+    pub fn enable(&mut self) {
+        unsafe { set_enabled(self.handle(), true) }
+    }
+    /// Disable the geometry so rays no longer hit it while keeping it
+    /// attached to the scene, the scene has to be committed again for
+    /// the change to take effect.
+    pub fn disable(&mut self) {
+        unsafe { set_enabled(self.handle(), false) }
+    }
+    pub fn is_enabled(&self) -> bool {
+        unsafe { state(self.handle()).enabled }
+    }
+    /// Set the quality of the BVH built over the geometry, defaults to
+    /// `MEDIUM`. `REFIT` can be used for deforming meshes to refit the
+    /// existing BVH instead of rebuilding it.
+    pub fn set_build_quality(&mut self, quality: BuildQuality) {
+        unsafe { set_build_quality(self.handle(), quality) }
+    }
+    pub fn build_quality(&self) -> BuildQuality {
+        unsafe { state(self.handle()).build_quality }
+    }
+    /// Update the buffers changed since the geometry was last committed,
+    /// marking the geometries they're attached to as changed. Returns true
+    /// if any of the buffers were changed.
+    pub(crate) fn update_buffers(&self) -> bool {
+        match self {
+            Geometry::Triangle(ref m) => m.update_buffers(),
+            Geometry::Quad(ref q) => q.update_buffers(),
+            Geometry::Subdiv(ref s) => s.update_buffers(),
+            Geometry::Grid(ref g) => g.update_buffers(),
+            Geometry::LinearCurve(ref lc) => lc.update_buffers(),
+            Geometry::BsplineCurve(ref bsc) => bsc.update_buffers(),
+            Geometry::BezierCurve(ref bzc) => bzc.update_buffers(),
+            Geometry::HermiteCurve(ref hc) => hc.update_buffers(),
+            Geometry::CatmullRomCurve(ref crc) => crc.update_buffers(),
+            Geometry::Points(ref p) => p.update_buffers(),
+            Geometry::Instance(_) | Geometry::User(_) => false,
         }
     }
+    /// Check if the geometry was changed since it was last committed,
+    /// clearing the flag
+    pub(crate) fn take_changed(&self) -> bool {
+        unsafe { take_changed(self.handle()) }
+    }
+    unsafe fn data(&mut self) -> &mut GeometryData<'a> {
+        geometry_data(self.handle())
+    }
 }
 
 impl<'a> Drop for Geometry<'a> {
     fn drop(&mut self) {
         unsafe {
-            rtcReleaseGeometry(self.handle());
+            release_geometry(self.handle());
         }
     }
 }
@@ -59,3 +487,338 @@ impl<'a> PartialEq<Geometry<'a>> for Geometry<'a> {
 }
 
 impl<'a> Eq for Geometry<'a> {}
+
+/// Check for errors creating and setting up the geometry `handle`,
+/// releasing it if an error occured.
+/// Create a new geometry of `geom_type`. Errors left on the thread by
+/// earlier calls are cleared first, so `check_new_geometry` only reports
+/// errors from setting up this geometry.
+pub(crate) unsafe fn new_geometry(device: &Device, geom_type: GeometryType) -> Result<RTCGeometry> {
+    device.clear_error();
+    let handle = rtcNewGeometry(device.handle, geom_type);
+    if handle.is_null() {
+        return Err(device.error_or("Failed to create geometry"));
+    }
+    Ok(handle)
+}
+
+pub(crate) unsafe fn check_new_geometry(device: &Device, handle: RTCGeometry) -> Result<()> {
+    let res = device.get_error();
+    if res.is_err() && !handle.is_null() {
+        release_geometry(handle);
+    }
+    res
+}
+
+/// Release the geometry `handle` and free its data. The geometry is
+/// detached from the buffers it's attached to first, which retain it and
+/// may outlive it, so changing them later doesn't touch the freed data.
+unsafe fn release_geometry(handle: RTCGeometry) {
+    let data = rtcGetGeometryUserData(handle) as *mut GeometryData;
+    if !data.is_null() {
+        for state in (*data).buffers.iter().filter_map(Weak::upgrade) {
+            state.detach(handle);
+        }
+        rtcSetGeometryUserData(handle, ptr::null_mut());
+        drop(Box::from_raw(data));
+    }
+    rtcReleaseGeometry(handle);
+}
+
+/// Check the number of time steps of an animated geometry is
+/// between 1 and `RTC_MAX_TIME_STEP_COUNT`.
+pub(crate) fn check_time_steps(time_steps: u32) -> Result<()> {
+    if time_steps == 0 || time_steps > RTC_MAX_TIME_STEP_COUNT {
+        Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            &format!(
+                "time step count must be between 1 and {}, got {}",
+                RTC_MAX_TIME_STEP_COUNT, time_steps
+            ),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Attach the buffers for the time steps after the first of an animated
+/// geometry, `buffers[i]` is used for time step `i + 1`.
+pub(crate) unsafe fn attach_time_step_buffers<T>(
+    handle: RTCGeometry,
+    buffers: &mut [Buffer<T>],
+    buf_type: BufferType,
+    format: Format,
+    count: usize,
+) {
+    for (i, buffer) in buffers.iter_mut().enumerate() {
+        let slot = i as u32 + 1;
+        rtcSetGeometryBuffer(
+            handle,
+            buf_type,
+            slot,
+            buffer.format().unwrap_or(format),
+            buffer.handle,
+            buffer.offset(),
+            buffer.stride(),
+            count,
+        );
+        buffer.set_attachment(handle, buf_type, slot);
+    }
+}
+
+/// Attach `buffer` to `slot` of the `buf_type` buffers of the geometry
+/// `handle`, with the buffer's format if it's a view or `format` otherwise.
+pub(crate) unsafe fn attach_buffer<T>(
+    handle: RTCGeometry,
+    buffer: &mut Buffer<T>,
+    buf_type: BufferType,
+    slot: u32,
+    format: Format,
+) {
+    rtcSetGeometryBuffer(
+        handle,
+        buf_type,
+        slot,
+        buffer.format().unwrap_or(format),
+        buffer.handle,
+        buffer.offset(),
+        buffer.stride(),
+        buffer.len(),
+    );
+    buffer.set_attachment(handle, buf_type, slot);
+}
+
+/// The data attached to an Embree geometry as its user pointer, which
+/// is passed to the callbacks set on the geometry. It's owned by the
+/// `Geometry` and released when it's dropped.
+pub(crate) struct GeometryData<'a> {
+    /// The `UserPrimitives` of a `UserGeometry`, the callbacks know its type
+    pub(crate) primitives: *const raw::c_void,
+    pub(crate) intersect_filter: Option<Box<FilterFn<'a>>>,
+    pub(crate) occluded_filter: Option<Box<FilterFn<'a>>>,
+    /// The displacement callback of a `SubdivMesh` and the maximum
+    /// distance it may move the surface
+    pub(crate) displacement: Option<Box<DisplacementFn<'a>>>,
+    pub(crate) displacement_bound: f32,
+    pub(crate) state: GeometryState,
+    /// Set when a setting of the geometry which requires committing it
+    /// again is changed, e.g. an instance's transform
+    pub(crate) changed: AtomicBool,
+    /// Set once the geometry has been committed, after which Embree's
+    /// topology queries can be used
+    pub(crate) committed: AtomicBool,
+    /// The state of the buffers attached to the geometry, so it can be
+    /// detached from them when it's released
+    pub(crate) buffers: Vec<Weak<BufferState>>,
+}
+
+/// The settings of a geometry which Embree doesn't let us query back
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct GeometryState {
+    pub(crate) mask: u32,
+    pub(crate) enabled: bool,
+    pub(crate) build_quality: BuildQuality,
+}
+
+impl Default for GeometryState {
+    fn default() -> GeometryState {
+        GeometryState {
+            mask: u32::MAX,
+            enabled: true,
+            build_quality: BuildQuality::MEDIUM,
+        }
+    }
+}
+
+impl<'a> GeometryData<'a> {
+    pub(crate) fn new() -> GeometryData<'a> {
+        GeometryData {
+            primitives: ptr::null(),
+            intersect_filter: None,
+            occluded_filter: None,
+            displacement: None,
+            displacement_bound: 0.0,
+            state: GeometryState::default(),
+            changed: AtomicBool::new(false),
+            committed: AtomicBool::new(false),
+            buffers: Vec::new(),
+        }
+    }
+}
+
+/// Get the data attached as the user pointer of the geometry `handle`,
+/// creating it if it hasn't been set yet.
+pub(crate) unsafe fn geometry_data<'a>(handle: RTCGeometry) -> &'a mut GeometryData<'a> {
+    let mut data = rtcGetGeometryUserData(handle) as *mut GeometryData<'a>;
+    if data.is_null() {
+        data = Box::into_raw(Box::new(GeometryData::new()));
+        rtcSetGeometryUserData(handle, data as *mut raw::c_void);
+    }
+    &mut *data
+}
+
+/// Get the state of the geometry `handle`, without creating its data
+/// if the geometry still has the default settings.
+unsafe fn state(handle: RTCGeometry) -> GeometryState {
+    let data = rtcGetGeometryUserData(handle) as *const GeometryData;
+    if data.is_null() {
+        GeometryState::default()
+    } else {
+        (*data).state
+    }
+}
+
+/// Mark the geometry `handle` as changed so it's committed again when its
+/// scene is next committed.
+pub(crate) unsafe fn mark_changed(handle: RTCGeometry) {
+    geometry_data(handle).changed.store(true, Ordering::Relaxed);
+}
+
+/// Mark the geometry `handle` as changed if its data is still set, for
+/// callers holding a handle which may outlive the `Geometry`.
+pub(crate) unsafe fn mark_changed_if_alive(handle: RTCGeometry) {
+    let data = rtcGetGeometryUserData(handle) as *const GeometryData;
+    if !data.is_null() {
+        (*data).changed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Check if the geometry `handle` was marked as changed, clearing the flag
+unsafe fn take_changed(handle: RTCGeometry) -> bool {
+    let data = rtcGetGeometryUserData(handle) as *const GeometryData;
+    !data.is_null() && (*data).changed.swap(false, Ordering::Relaxed)
+}
+
+/// Commit the geometry `handle` and record that it was committed
+pub(crate) unsafe fn commit_geometry(handle: RTCGeometry) {
+    rtcCommitGeometry(handle);
+    geometry_data(handle)
+        .committed
+        .store(true, Ordering::Relaxed);
+}
+
+/// Check if the geometry `handle` has been committed
+pub(crate) unsafe fn is_committed(handle: RTCGeometry) -> bool {
+    let data = rtcGetGeometryUserData(handle) as *const GeometryData;
+    !data.is_null() && (*data).committed.load(Ordering::Relaxed)
+}
+
+unsafe fn set_mask(handle: RTCGeometry, mask: u32) {
+    rtcSetGeometryMask(handle, mask);
+    let data = geometry_data(handle);
+    data.state.mask = mask;
+    data.changed.store(true, Ordering::Relaxed);
+}
+
+unsafe fn set_enabled(handle: RTCGeometry, enabled: bool) {
+    if enabled {
+        rtcEnableGeometry(handle);
+    } else {
+        rtcDisableGeometry(handle);
+    }
+    geometry_data(handle).state.enabled = enabled;
+}
+
+unsafe fn set_build_quality(handle: RTCGeometry, quality: BuildQuality) {
+    rtcSetGeometryBuildQuality(handle, quality);
+    let data = geometry_data(handle);
+    data.state.build_quality = quality;
+    data.changed.store(true, Ordering::Relaxed);
+}
+
+/// Implement the mask, enabled and build quality settings of `Geometry`
+/// on the individual geometry types, sharing the same tracked state.
+macro_rules! impl_geometry_state {
+    ($($module:ident::$geom:ident),*) => {
+        $(
+            impl<'a> $module::$geom<'a> {
+                /// Set the mask of the geometry, see `Geometry::set_mask`
+                pub fn set_mask(&mut self, mask: u32) {
+                    unsafe { set_mask(self.handle, mask) }
+                }
+                pub fn mask(&self) -> u32 {
+                    unsafe { state(self.handle).mask }
+                }
+                /// Enable the geometry, see `Geometry::enable`
+                pub fn enable(&mut self) {
+                    unsafe { set_enabled(self.handle, true) }
+                }
+                /// Disable the geometry, see `Geometry::disable`
+                pub fn disable(&mut self) {
+                    unsafe { set_enabled(self.handle, false) }
+                }
+                pub fn is_enabled(&self) -> bool {
+                    unsafe { state(self.handle).enabled }
+                }
+                /// Set the BVH build quality, see `Geometry::set_build_quality`
+                pub fn set_build_quality(&mut self, quality: BuildQuality) {
+                    unsafe { set_build_quality(self.handle, quality) }
+                }
+                pub fn build_quality(&self) -> BuildQuality {
+                    unsafe { state(self.handle).build_quality }
+                }
+            }
+        )*
+    };
+}
+
+impl_geometry_state!(
+    triangle_mesh::TriangleMesh,
+    quad_mesh::QuadMesh,
+    subdiv_mesh::SubdivMesh,
+    grid_mesh::GridMesh,
+    instance::Instance,
+    linear_curve::LinearCurve,
+    bspline_curve::BsplineCurve,
+    bezier_curve::BezierCurve,
+    hermite_curve::HermiteCurve,
+    catmull_rom_curve::CatmullRomCurve,
+    points::Points,
+    user_geometry::UserGeometry
+);
+
+#[test]
+fn test_interpolate() {
+    use crate::{Device, TriangleMesh};
+    use ultraviolet::Vec3;
+
+    let device = Device::new();
+    let mut mesh = TriangleMesh::unanimated(&device, 1, 3);
+    {
+        let verts = mesh.vertex_buffer.as_mut_slice();
+        verts[0] = Vec3::new(0.0, 0.0, 0.0);
+        verts[1] = Vec3::new(1.0, 0.0, 0.0);
+        verts[2] = Vec3::new(0.0, 1.0, 0.0);
+        mesh.index_buffer.as_mut_slice()[0] = [0, 1, 2];
+    }
+    let mut geometry = Geometry::Triangle(mesh);
+    let slot = geometry.add_vertex_attribute(1).unwrap();
+    {
+        let attribute = &mut geometry.vertex_attributes_mut()[slot as usize];
+        attribute.get_mut(0)[0] = 1.0;
+        attribute.get_mut(1)[0] = 2.0;
+        attribute.get_mut(2)[0] = 4.0;
+    }
+    let err = geometry.interpolate(0, 0.25, 0.5, slot).unwrap_err();
+    assert_eq!(err.code, Error::INVALID_OPERATION);
+    geometry.commit();
+
+    // The attribute is blended with the barycentrics (1 - u - v, u, v)
+    let res = geometry.interpolate(0, 0.25, 0.5, slot).unwrap();
+    assert!((res.value[0] - (0.25 * 1.0 + 0.25 * 2.0 + 0.5 * 4.0)).abs() < 1e-5);
+    assert!((res.du[0] - 1.0).abs() < 1e-5);
+    assert!((res.dv[0] - 3.0).abs() < 1e-5);
+    let batch = geometry
+        .interpolate_n(&[0, 0], &[0.0, 1.0], &[0.0, 0.0], slot)
+        .unwrap();
+    assert!((batch[0].value[0] - 1.0).abs() < 1e-5);
+    assert!((batch[1].value[0] - 2.0).abs() < 1e-5);
+
+    let err = geometry.interpolate(0, 0.25, 0.5, slot + 1).unwrap_err();
+    assert_eq!(err.code, Error::INVALID_ARGUMENT);
+    // The mesh only has triangle 0
+    let err = geometry.interpolate(1, 0.25, 0.5, slot).unwrap_err();
+    assert_eq!(err.code, Error::INVALID_ARGUMENT);
+    let err = geometry.interpolate_vertex(1, 0.25, 0.5).unwrap_err();
+    assert_eq!(err.code, Error::INVALID_ARGUMENT);
+}
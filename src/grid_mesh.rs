@@ -0,0 +1,187 @@
+use ultraviolet::{Vec2, Vec3};
+
+use crate::buffer::{update_all, Buffer};
+use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, new_geometry,
+};
+use crate::sys::*;
+use crate::vertex_attribute::VertexAttribute;
+use crate::{BufferType, Error, Format, GeometryType};
+
+pub type Grid = RTCGrid;
+
+impl Grid {
+    /// Create a grid of `width * height` vertices starting at `start_vertex`
+    /// in the vertex buffer, with rows `stride` vertices apart.
+    pub fn new(start_vertex: u32, stride: u32, width: u16, height: u16) -> Grid {
+        RTCGrid {
+            startVertexID: start_vertex,
+            stride,
+            width,
+            height,
+        }
+    }
+    /// Get the index in the vertex buffer of vertex `x, y` of the grid
+    pub fn vertex_id(&self, x: u32, y: u32) -> u32 {
+        self.startVertexID + y * self.stride + x
+    }
+    /// Map the hit `u, v`, which span [0, 1] over the whole grid,
+    /// back to continuous coordinates in the grid's vertices.
+    pub fn grid_coords(&self, u: f32, v: f32) -> Vec2 {
+        Vec2::new(
+            u * (self.width as f32 - 1.0),
+            v * (self.height as f32 - 1.0),
+        )
+    }
+}
+
+pub struct GridMesh<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) handle: RTCGeometry,
+    /// The vertices shared by the grids
+    pub vertex_buffer: Buffer<'a, Vec3>,
+    /// The vertex buffers for the time steps after the first of an animated mesh
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
+    pub index_buffer: Buffer<'a, Grid>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
+}
+
+impl<'a> GridMesh<'a> {
+    pub fn unanimated(device: &'a Device, num_grids: usize, num_verts: usize) -> GridMesh<'a> {
+        GridMesh::try_unanimated(device, num_grids, num_verts).expect("Failed to create GridMesh")
+    }
+    pub fn try_unanimated(
+        device: &'a Device,
+        num_grids: usize,
+        num_verts: usize,
+    ) -> Result<GridMesh<'a>> {
+        GridMesh::try_animated(device, num_grids, num_verts, 1)
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur,
+    /// panics if the mesh can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
+        device: &'a Device,
+        num_grids: usize,
+        num_verts: usize,
+        time_steps: u32,
+    ) -> GridMesh<'a> {
+        GridMesh::try_animated(device, num_grids, num_verts, time_steps)
+            .expect("Failed to create GridMesh")
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur. The
+    /// vertices of the first time step are in `vertex_buffer` and the
+    /// following ones in `motion_vertex_buffers`.
+    pub fn try_animated(
+        device: &'a Device,
+        num_grids: usize,
+        num_verts: usize,
+        time_steps: u32,
+    ) -> Result<GridMesh<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let index_buffer = Buffer::try_new(device, num_grids)?;
+        GridMesh::with_buffers(device, vertex_buffer, motion_vertex_buffers, index_buffer)
+    }
+    /// Create a mesh using existing vertex and grid buffers, e.g. buffers
+    /// sharing the user's memory made with `Buffer::shared` or views of
+    /// a larger buffer made with `Buffer::view`.
+    pub fn from_buffers(
+        device: &'a Device,
+        vertex_buffer: Buffer<'a, Vec3>,
+        index_buffer: Buffer<'a, Grid>,
+    ) -> Result<GridMesh<'a>> {
+        GridMesh::with_buffers(device, vertex_buffer, Vec::new(), index_buffer)
+    }
+    fn with_buffers(
+        device: &'a Device,
+        mut vertex_buffer: Buffer<'a, Vec3>,
+        mut motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
+        mut index_buffer: Buffer<'a, Grid>,
+    ) -> Result<GridMesh<'a>> {
+        let num_verts = vertex_buffer.len();
+        let time_steps = motion_vertex_buffers.len() as u32 + 1;
+        let h = unsafe { new_geometry(device, GeometryType::GRID)? };
+        unsafe {
+            attach_buffer(h, &mut vertex_buffer, BufferType::VERTEX, 0, Format::FLOAT3);
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
+                h,
+                &mut motion_vertex_buffers,
+                BufferType::VERTEX,
+                Format::FLOAT3,
+                num_verts,
+            );
+            attach_buffer(h, &mut index_buffer, BufferType::GRID, 0, Format::GRID);
+            check_new_geometry(device, h)?;
+        }
+        Ok(GridMesh {
+            device,
+            handle: h,
+            vertex_buffer,
+            motion_vertex_buffers,
+            index_buffer,
+            vertex_attributes: Vec::new(),
+        })
+    }
+    /// Create a single grid mesh from a heightfield of `width * height`
+    /// samples stored row by row in `heights`. Sample `x, y` is placed at
+    /// `(x * cell_size.x, heights[y * width + x], y * cell_size.y)`.
+    pub fn heightfield(
+        device: &'a Device,
+        width: u16,
+        height: u16,
+        heights: &[f32],
+        cell_size: Vec2,
+    ) -> Result<GridMesh<'a>> {
+        let num_verts = width as usize * height as usize;
+        if width < 2 || height < 2 || heights.len() != num_verts {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!(
+                    "heightfield must have at least 2x2 samples and {} heights, got {}",
+                    num_verts,
+                    heights.len()
+                ),
+            ));
+        }
+        let mut mesh = GridMesh::try_unanimated(device, 1, num_verts)?;
+        {
+            let verts = mesh.vertex_buffer.as_mut_slice();
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let i = y * width as usize + x;
+                    verts[i] =
+                        Vec3::new(x as f32 * cell_size.x, heights[i], y as f32 * cell_size.y);
+                }
+            }
+        }
+        mesh.index_buffer.as_mut_slice()[0] = Grid::new(0, width as u32, width, height);
+        Ok(mesh)
+    }
+    /// Get the grid `prim_id`
+    pub fn grid(&self, prim_id: u32) -> Grid {
+        self.index_buffer.as_slice()[prim_id as usize]
+    }
+    /// Map the hit `u, v` on the grid `prim_id` back to continuous
+    /// coordinates in the grid's vertices.
+    pub fn grid_coords(&self, prim_id: u32, u: f32, v: f32) -> Vec2 {
+        self.grid(prim_id).grid_coords(u, v)
+    }
+    /// Update the buffers changed since the mesh was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | self.index_buffer.update()
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
+    }
+}
+
+unsafe impl<'a> Sync for GridMesh<'a> {}
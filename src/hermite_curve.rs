@@ -1,19 +1,32 @@
+use std::ptr;
+
 use ultraviolet::{Vec2, Vec3, Vec4};
 
-use crate::buffer::Buffer;
+use crate::buffer::{update_all, Buffer};
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::error::Result;
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, new_geometry,
+};
 use crate::sys::*;
+use crate::vertex_attribute::VertexAttribute;
 use crate::{BufferType, CurveType, Format, GeometryType};
 
 pub struct HermiteCurve<'a> {
-    device: &'a Device,
+    pub(crate) device: &'a Device,
     pub(crate) handle: RTCGeometry,
     pub vertex_buffer: Buffer<'a, Vec4>,
+    /// The vertex buffers for the time steps after the first of an animated curve
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec4>>,
     pub index_buffer: Buffer<'a, u32>,
     pub tangent_buffer: Buffer<'a, Vec4>,
+    pub motion_tangent_buffers: Vec<Buffer<'a, Vec4>>,
     pub normal_derivative_buffer: Option<Buffer<'a, Vec3>>,
+    pub motion_normal_derivative_buffers: Vec<Buffer<'a, Vec3>>,
     pub normal_buffer: Option<Buffer<'a, Vec3>>,
+    pub motion_normal_buffers: Vec<Buffer<'a, Vec3>>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
 }
 
 impl<'a> HermiteCurve<'a> {
@@ -23,12 +36,22 @@ impl<'a> HermiteCurve<'a> {
         num_verts: usize,
         use_normals: bool,
     ) -> HermiteCurve<'a> {
-        HermiteCurve::unanimated(
+        HermiteCurve::try_flat(device, num_segments, num_verts, use_normals)
+            .expect("Failed to create HermiteCurve")
+    }
+    pub fn try_flat(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        use_normals: bool,
+    ) -> Result<HermiteCurve<'a>> {
+        HermiteCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::Flat,
             use_normals,
+            1,
         )
     }
     pub fn round(
@@ -37,12 +60,22 @@ impl<'a> HermiteCurve<'a> {
         num_verts: usize,
         use_normals: bool,
     ) -> HermiteCurve<'a> {
-        HermiteCurve::unanimated(
+        HermiteCurve::try_round(device, num_segments, num_verts, use_normals)
+            .expect("Failed to create HermiteCurve")
+    }
+    pub fn try_round(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        use_normals: bool,
+    ) -> Result<HermiteCurve<'a>> {
+        HermiteCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::Round,
             use_normals,
+            1,
         )
     }
     pub fn normal_oriented(
@@ -50,116 +83,222 @@ impl<'a> HermiteCurve<'a> {
         num_segments: usize,
         num_verts: usize,
     ) -> HermiteCurve<'a> {
-        HermiteCurve::unanimated(
+        HermiteCurve::try_normal_oriented(device, num_segments, num_verts)
+            .expect("Failed to create HermiteCurve")
+    }
+    pub fn try_normal_oriented(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+    ) -> Result<HermiteCurve<'a>> {
+        HermiteCurve::try_animated(
             device,
             num_segments,
             num_verts,
             CurveType::NormalOriented,
             true,
+            1,
         )
     }
 
-    fn unanimated(
+    /// Create a curve with `time_steps` vertex buffers for motion blur,
+    /// panics if the curve can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
         device: &'a Device,
         num_segments: usize,
         num_verts: usize,
         curve_type: CurveType,
         use_normals: bool,
+        time_steps: u32,
     ) -> HermiteCurve<'a> {
-        let h: RTCGeometry;
-        match curve_type {
-            CurveType::NormalOriented => {
-                h = unsafe {
-                    rtcNewGeometry(device.handle, GeometryType::NORMAL_ORIENTED_HERMITE_CURVE)
-                }
-            }
-            CurveType::Round => {
-                h = unsafe { rtcNewGeometry(device.handle, GeometryType::ROUND_HERMITE_CURVE) }
-            }
-            _ => h = unsafe { rtcNewGeometry(device.handle, GeometryType::FLAT_HERMITE_CURVE) },
+        HermiteCurve::try_animated(
+            device,
+            num_segments,
+            num_verts,
+            curve_type,
+            use_normals,
+            time_steps,
+        )
+        .expect("Failed to create HermiteCurve")
+    }
+    /// Create a curve with `time_steps` vertex buffers (and normal and
+    /// tangent buffers if used) for motion blur. The buffers of the first
+    /// time step are in `vertex_buffer` and the following ones in the
+    /// corresponding `motion_*` buffers.
+    pub fn try_animated(
+        device: &'a Device,
+        num_segments: usize,
+        num_verts: usize,
+        curve_type: CurveType,
+        use_normals: bool,
+        time_steps: u32,
+    ) -> Result<HermiteCurve<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let index_buffer = Buffer::try_new(device, num_segments)?;
+        let tangent_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_tangent_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let normal_buffer = if use_normals {
+            Some(Buffer::try_new(device, num_verts)?)
+        } else {
+            None
         };
-        let mut vertex_buffer = Buffer::new(device, num_verts);
-        let mut index_buffer = Buffer::new(device, num_segments);
-        let mut tangent_buffer = Buffer::new(device, num_verts);
-        let mut normal_derivative_buffer = None;
-        let mut normal_buffer = None;
-
+        let motion_normal_buffers = if use_normals {
+            (1..time_steps)
+                .map(|_| Buffer::try_new(device, num_verts))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        let normal_derivative_buffer = if use_normals {
+            Some(Buffer::try_new(device, num_verts)?)
+        } else {
+            None
+        };
+        let motion_normal_derivative_buffers = if use_normals {
+            (1..time_steps)
+                .map(|_| Buffer::try_new(device, num_verts))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        HermiteCurve {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers,
+            index_buffer,
+            tangent_buffer,
+            motion_tangent_buffers,
+            normal_derivative_buffer,
+            motion_normal_derivative_buffers,
+            normal_buffer,
+            motion_normal_buffers,
+            vertex_attributes: Vec::new(),
+        }
+        .create(curve_type)
+    }
+    /// Create a curve of `curve_type` using existing buffers, e.g. buffers
+    /// sharing the user's memory made with `Buffer::shared` or views of
+    /// a larger buffer made with `Buffer::view`. Normal oriented curves
+    /// need normal and normal derivative buffers.
+    pub fn from_buffers(
+        device: &'a Device,
+        curve_type: CurveType,
+        vertex_buffer: Buffer<'a, Vec4>,
+        index_buffer: Buffer<'a, u32>,
+        tangent_buffer: Buffer<'a, Vec4>,
+        normal_buffer: Option<Buffer<'a, Vec3>>,
+        normal_derivative_buffer: Option<Buffer<'a, Vec3>>,
+    ) -> Result<HermiteCurve<'a>> {
+        HermiteCurve {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers: Vec::new(),
+            index_buffer,
+            tangent_buffer,
+            motion_tangent_buffers: Vec::new(),
+            normal_derivative_buffer,
+            motion_normal_derivative_buffers: Vec::new(),
+            normal_buffer,
+            motion_normal_buffers: Vec::new(),
+            vertex_attributes: Vec::new(),
+        }
+        .create(curve_type)
+    }
+    /// Create the Embree geometry of `curve_type` for the curve's buffers
+    /// and attach them to it
+    fn create(mut self, curve_type: CurveType) -> Result<HermiteCurve<'a>> {
+        let geometry_type = match curve_type {
+            CurveType::NormalOriented => GeometryType::NORMAL_ORIENTED_HERMITE_CURVE,
+            CurveType::Round => GeometryType::ROUND_HERMITE_CURVE,
+            _ => GeometryType::FLAT_HERMITE_CURVE,
+        };
+        let h = unsafe { new_geometry(self.device, geometry_type)? };
+        let num_verts = self.vertex_buffer.len();
+        let time_steps = self.motion_vertex_buffers.len() as u32 + 1;
         unsafe {
-            rtcSetGeometryBuffer(
+            attach_buffer(
                 h,
+                &mut self.vertex_buffer,
                 BufferType::VERTEX,
                 0,
                 Format::FLOAT4,
-                vertex_buffer.handle,
-                0,
-                16,
+            );
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
+                h,
+                &mut self.motion_vertex_buffers,
+                BufferType::VERTEX,
+                Format::FLOAT4,
                 num_verts,
             );
-            vertex_buffer.set_attachment(h, BufferType::VERTEX, 0);
-
-            rtcSetGeometryBuffer(
+            attach_buffer(
                 h,
+                &mut self.index_buffer,
                 BufferType::INDEX,
                 0,
                 Format::UINT,
-                index_buffer.handle,
-                0,
-                4,
-                num_segments,
             );
-            index_buffer.set_attachment(h, BufferType::INDEX, 0);
-
-            rtcSetGeometryBuffer(
+            attach_buffer(
                 h,
+                &mut self.tangent_buffer,
                 BufferType::TANGENT,
                 0,
                 Format::FLOAT4,
-                tangent_buffer.handle,
-                0,
-                16,
+            );
+            attach_time_step_buffers(
+                h,
+                &mut self.motion_tangent_buffers,
+                BufferType::TANGENT,
+                Format::FLOAT4,
                 num_verts,
             );
-            tangent_buffer.set_attachment(h, BufferType::TANGENT, 0);
-
-            if use_normals {
-                let mut temp_normal_buffer = Buffer::new(device, num_verts);
-                rtcSetGeometryBuffer(
+            if let Some(ref mut normal_buffer) = self.normal_buffer {
+                attach_buffer(h, normal_buffer, BufferType::NORMAL, 0, Format::FLOAT3);
+                attach_time_step_buffers(
                     h,
+                    &mut self.motion_normal_buffers,
                     BufferType::NORMAL,
-                    0,
                     Format::FLOAT3,
-                    temp_normal_buffer.handle,
-                    0,
-                    12,
                     num_verts,
                 );
-                temp_normal_buffer.set_attachment(h, BufferType::NORMAL, 0);
-                normal_buffer = Some(temp_normal_buffer);
-
-                let mut temp_normal_derivative_buffer = Buffer::new(device, num_verts);
-                rtcSetGeometryBuffer(
+            }
+            if let Some(ref mut buffer) = self.normal_derivative_buffer {
+                attach_buffer(h, buffer, BufferType::NORMAL_DERIVATIVE, 0, Format::FLOAT3);
+                attach_time_step_buffers(
                     h,
+                    &mut self.motion_normal_derivative_buffers,
                     BufferType::NORMAL_DERIVATIVE,
-                    0,
                     Format::FLOAT3,
-                    temp_normal_derivative_buffer.handle,
-                    0,
-                    12,
                     num_verts,
                 );
-                temp_normal_derivative_buffer.set_attachment(h, BufferType::NORMAL_DERIVATIVE, 0);
-                normal_derivative_buffer = Some(temp_normal_derivative_buffer);
             }
+            check_new_geometry(self.device, h)?;
         }
-        HermiteCurve {
-            device: device,
-            handle: h,
-            vertex_buffer: vertex_buffer,
-            index_buffer: index_buffer,
-            tangent_buffer: tangent_buffer,
-            normal_derivative_buffer: normal_derivative_buffer,
-            normal_buffer: normal_buffer,
-        }
+        self.handle = h;
+        Ok(self)
+    }
+    /// Update the buffers changed since the curve was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | self.index_buffer.update()
+            | self.tangent_buffer.update()
+            | update_all(&self.motion_tangent_buffers)
+            | update_all(&self.normal_derivative_buffer)
+            | update_all(&self.motion_normal_derivative_buffers)
+            | update_all(&self.normal_buffer)
+            | update_all(&self.motion_normal_buffers)
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
     }
 }
 
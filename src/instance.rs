@@ -1,44 +1,158 @@
 use std::os::raw;
 
-use ultraviolet::Mat4;
+use ultraviolet::{Mat4, Rotor3, Vec3};
 
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::error::Result;
+use crate::geometry::{check_new_geometry, check_time_steps, mark_changed, new_geometry};
 use crate::scene::{CommittedScene, Scene};
 use crate::sys::*;
 use crate::{BufferType, Format, GeometryType};
 
+/// An instance of a committed scene. The instanced scene may itself
+/// contain instances when the `multi-level-instancing` feature is enabled,
+/// up to `MAX_INSTANCE_LEVEL_COUNT` levels deep.
 pub struct Instance<'a> {
-    device: &'a Device,
+    pub(crate) device: &'a Device,
     pub(crate) handle: RTCGeometry,
     /// The scene being instanced
-    scene: &'a CommittedScene<'a>,
+    pub(crate) scene: &'a CommittedScene<'a>,
 }
 
 impl<'a> Instance<'a> {
     pub fn unanimated(device: &'a Device, scene: &'a CommittedScene) -> Instance<'a> {
-        let h = unsafe { rtcNewGeometry(device.handle, GeometryType::INSTANCE) };
+        Instance::try_unanimated(device, scene).expect("Failed to create Instance")
+    }
+    pub fn try_unanimated(device: &'a Device, scene: &'a CommittedScene) -> Result<Instance<'a>> {
+        Instance::try_animated(device, scene, 1)
+    }
+    /// Create an instance with `time_steps` transforms for motion blur,
+    /// set with `set_transform_at`, panics if the instance can't be
+    /// created. See `try_animated` for a fallible version.
+    pub fn animated(
+        device: &'a Device,
+        scene: &'a CommittedScene,
+        time_steps: u32,
+    ) -> Instance<'a> {
+        Instance::try_animated(device, scene, time_steps).expect("Failed to create Instance")
+    }
+    /// Create an instance with `time_steps` transforms for motion blur,
+    /// set with `set_transform_at`. Returns an error if `time_steps` is
+    /// not between 1 and `RTC_MAX_TIME_STEP_COUNT` or the instance can't
+    /// be created.
+    pub fn try_animated(
+        device: &'a Device,
+        scene: &'a CommittedScene,
+        time_steps: u32,
+    ) -> Result<Instance<'a>> {
+        check_time_steps(time_steps)?;
+        let h = unsafe { new_geometry(device, GeometryType::INSTANCE)? };
         unsafe {
             rtcSetGeometryInstancedScene(h, scene.scene.handle);
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            check_new_geometry(device, h)?;
         }
-        Instance {
+        Ok(Instance {
             device: device,
             handle: h,
             scene: scene,
+        })
+    }
+    /// Get the transform of the instance at `time`, interpolated between
+    /// the time steps of animated instances.
+    pub fn transform(&self, time: f32) -> Mat4 {
+        let mut mat = [0.0; 16];
+        unsafe {
+            rtcGetGeometryTransform(
+                self.handle,
+                time,
+                Format::FLOAT4X4_COLUMN_MAJOR,
+                mat.as_mut_ptr() as *mut raw::c_void,
+            );
         }
+        Mat4::from(mat)
     }
+    /// Set the transform of the first time step, which is the only
+    /// one for unanimated instances.
     pub fn set_transform(&mut self, transform: &Mat4) {
+        self.set_transform_at(0, transform);
+    }
+    /// Set the transform of `time_step` for an animated instance, the
+    /// transform is interpolated between the time steps.
+    pub fn set_transform_at(&mut self, time_step: u32, transform: &Mat4) {
         let mat: &[f32; 16] = transform.as_array();
-        // Will this be fine if we don't set the number of timesteps? Default should be 1?
         unsafe {
             rtcSetGeometryTransform(
                 self.handle,
-                0,
+                time_step,
                 Format::FLOAT4X4_COLUMN_MAJOR,
                 mat.as_ptr() as *const raw::c_void,
             );
+            mark_changed(self.handle);
+        }
+    }
+    /// Set the transform of the first time step from its decomposition,
+    /// see `set_transform_decomposed_at`.
+    pub fn set_transform_decomposed(
+        &mut self,
+        scale: Vec3,
+        shear: Vec3,
+        rotation: Rotor3,
+        translation: Vec3,
+    ) {
+        self.set_transform_decomposed_at(0, scale, shear, rotation, translation);
+    }
+    /// Set the transform of `time_step` from its decomposition into a scale,
+    /// the shear factors `(xy, xz, yz)`, a rotation and a translation, which
+    /// are applied in that order. Embree interpolates the rotation of
+    /// decomposed transforms spherically between time steps, so rotating
+    /// instances are motion blurred along an arc instead of a line.
+    pub fn set_transform_decomposed_at(
+        &mut self,
+        time_step: u32,
+        scale: Vec3,
+        shear: Vec3,
+        rotation: Rotor3,
+        translation: Vec3,
+    ) {
+        // ultraviolet gives the quaternion as [i, j, k, r]
+        let q = rotation.into_quaternion_array();
+        let qd = RTCQuaternionDecomposition {
+            scale_x: scale.x,
+            scale_y: scale.y,
+            scale_z: scale.z,
+            skew_xy: shear.x,
+            skew_xz: shear.y,
+            skew_yz: shear.z,
+            shift_x: 0.0,
+            shift_y: 0.0,
+            shift_z: 0.0,
+            quaternion_r: q[3],
+            quaternion_i: q[0],
+            quaternion_j: q[1],
+            quaternion_k: q[2],
+            translation_x: translation.x,
+            translation_y: translation.y,
+            translation_z: translation.z,
+        };
+        unsafe {
+            rtcSetGeometryTransformQuaternion(self.handle, time_step, &qd);
+            mark_changed(self.handle);
         }
     }
+    /// Get the transform from world space to the instance's space at `time`
+    pub fn inverse_transform(&self, time: f32) -> Mat4 {
+        self.transform(time).inversed()
+    }
+    /// Get the matrix transforming normals from the instance's space
+    /// to world space at `time`, the inverse transpose of the transform.
+    pub fn normal_transform(&self, time: f32) -> Mat4 {
+        self.inverse_transform(time).transposed()
+    }
+    /// Transform the normal `n` of a hit in the instance to world space
+    pub fn normal_to_world(&self, n: Vec3, time: f32) -> Vec3 {
+        self.normal_transform(time).transform_vec3(n).normalized()
+    }
 }
 
 unsafe impl<'a> Sync for Instance<'a> {}
@@ -11,18 +11,27 @@
 //! See the [examples/](https://github.com/Twinklebear/embree-rs/tree/master/examples)
 //! for some example applications using the bindings.
 
-use std::{alloc, mem};
+use std::{alloc, mem, panic, process};
 
 pub mod bezier_curve;
+pub mod bounds;
 pub mod bspline_curve;
 pub mod buffer;
+pub mod bvh;
 pub mod catmull_rom_curve;
+pub mod collide;
 pub mod curve;
 pub mod device;
+pub mod displacement;
+pub mod error;
+pub mod filter;
 pub mod geometry;
+pub mod grid_mesh;
 pub mod hermite_curve;
 pub mod instance;
 pub mod linear_curve;
+pub mod point_query;
+pub mod points;
 pub mod quad_mesh;
 pub mod ray;
 pub mod ray_packet;
@@ -36,27 +45,40 @@ pub mod subdiv_mesh;
 #[allow(non_snake_case)]
 pub mod sys;
 pub mod triangle_mesh;
+pub mod user_geometry;
+pub mod vertex_attribute;
 pub use bezier_curve::BezierCurve;
+pub use bounds::{Bounds, LinearBounds};
 pub use bspline_curve::BsplineCurve;
-pub use buffer::Buffer;
+pub use buffer::{Buffer, BufferView, MappedBuffer};
+pub use bvh::{BuildPrimitive, Bvh, BvhConfig};
 pub use catmull_rom_curve::CatmullRomCurve;
-pub use curve::CurveType;
-pub use device::Device;
+pub use collide::Collision;
+pub use curve::{CurveBasis, CurveType};
+pub use device::{Device, DeviceConfig, FrequencyLevel, Isa};
+pub use displacement::{DisplacementArgs, DisplacementFn};
+pub use error::{EmbreeError, Result};
+pub use filter::{FilterArgs, FilterContext, FilterFn};
 pub use geometry::Geometry;
+pub use grid_mesh::{Grid, GridMesh};
 pub use hermite_curve::HermiteCurve;
 pub use instance::Instance;
 pub use linear_curve::LinearCurve;
+pub use point_query::{ClosestPoint, PointQuery, PointQueryArgs, PointQueryContext};
+pub use points::{PointType, Points};
 pub use quad_mesh::QuadMesh;
 pub use ray::{Hit, IntersectContext, Ray, RayHit};
-pub use ray_packet::{Hit4, Ray4, RayHit4};
-pub use ray_stream::{HitN, RayHitN, RayN};
-pub use scene::{CommittedScene, Scene};
+pub use ray_packet::{Hit16, Hit4, Hit8, Ray16, Ray4, Ray8, RayHit16, RayHit4, RayHit8};
+pub use ray_stream::{HitN, HitNRef, RayHitN, RayN, RayNRef};
+pub use scene::{CommittedScene, Scene, SceneOptions};
 pub use soa_ray::{
     SoAHit, SoAHitIter, SoAHitIterMut, SoAHitRef, SoARay, SoARayIter, SoARayIterMut, SoARayRef,
     SoARayRefMut,
 };
-pub use subdiv_mesh::SubdivMesh;
+pub use subdiv_mesh::{HalfEdge, SubdivMesh};
 pub use triangle_mesh::TriangleMesh;
+pub use user_geometry::{IntersectArgs, OccludedArgs, UserGeometry, UserPrimitives};
+pub use vertex_attribute::{Interpolated, VertexAttribute};
 
 // Pull in some cleaned up enum and bitfield types directly,
 // with prettier aliases
@@ -73,6 +95,10 @@ pub use sys::RTCCurveFlags as CurveFlags;
 pub use sys::RTCIntersectContextFlags as IntersectContextFlags;
 pub use sys::RTCSceneFlags as SceneFlags;
 
+/// The number of levels of nested instances supported, enable the
+/// `multi-level-instancing` feature to support nesting instances
+pub const MAX_INSTANCE_LEVEL_COUNT: usize = sys::RTC_MAX_INSTANCE_LEVEL_COUNT as usize;
+
 /// Utility for making specifically aligned vectors
 pub fn aligned_vector<T>(len: usize, align: usize) -> Vec<T> {
     let t_size = mem::size_of::<T>();
@@ -96,6 +122,15 @@ pub fn aligned_vector_init<T: Copy>(len: usize, align: usize, init: T) -> Vec<T>
     v
 }
 
+/// Run a user callback invoked from Embree, aborting if it panics since
+/// unwinding back across the FFI boundary into Embree is undefined behavior.
+pub(crate) fn catch_unwind_abort<R, F: FnOnce() -> R>(f: F) -> R {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(r) => r,
+        Err(_) => process::abort(),
+    }
+}
+
 #[test]
 fn test_aligned_vector_alloc() {
     let v = aligned_vector_init::<f32>(24, 16, 1.0);
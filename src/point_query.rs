@@ -0,0 +1,225 @@
+use std::os::raw;
+
+use ultraviolet::{Mat4, Vec3};
+
+use crate::catch_unwind_abort;
+use crate::sys;
+use crate::MAX_INSTANCE_LEVEL_COUNT;
+
+pub type PointQuery = sys::RTCPointQuery;
+pub type PointQueryContext = sys::RTCPointQueryContext;
+
+impl PointQuery {
+    /// Create a query for primitives within `radius` of the point `p`
+    pub fn new(p: Vec3, radius: f32) -> PointQuery {
+        PointQuery::at_time(p, radius, 0.0)
+    }
+    /// Create a query at `time` for scenes with motion blur
+    pub fn at_time(p: Vec3, radius: f32, time: f32) -> PointQuery {
+        sys::RTCPointQuery {
+            x: p.x,
+            y: p.y,
+            z: p.z,
+            time,
+            radius,
+        }
+    }
+    pub fn point(&self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+}
+
+impl PointQueryContext {
+    pub fn new() -> PointQueryContext {
+        sys::RTCPointQueryContext {
+            world2inst: [[0.0; 16]; MAX_INSTANCE_LEVEL_COUNT],
+            inst2world: [[0.0; 16]; MAX_INSTANCE_LEVEL_COUNT],
+            instID: [u32::MAX; MAX_INSTANCE_LEVEL_COUNT],
+            instStackSize: 0,
+        }
+    }
+}
+
+impl Default for PointQueryContext {
+    fn default() -> PointQueryContext {
+        PointQueryContext::new()
+    }
+}
+
+/// The arguments passed to the point query callback for each primitive
+/// whose bounds overlap the query, wrapping `RTCPointQueryFunctionArguments`.
+///
+/// When the query enters an instance whose transform is a similarity
+/// transform the query point and radius are transformed into the
+/// instance's space and `similarity_scale` is the scale from world to
+/// instance space. Otherwise the query stays in world space and
+/// `similarity_scale` is 0, the instance transforms are available through
+/// `instance_to_world` and `world_to_instance` to transform the primitive.
+pub struct PointQueryArgs<'a> {
+    query: &'a mut PointQuery,
+    prim_id: u32,
+    geom_id: u32,
+    context: &'a PointQueryContext,
+    similarity_scale: f32,
+}
+
+impl<'a> PointQueryArgs<'a> {
+    pub fn prim_id(&self) -> u32 {
+        self.prim_id
+    }
+    pub fn geom_id(&self) -> u32 {
+        self.geom_id
+    }
+    /// The query point, in instance space when in a similarity transformed instance
+    pub fn point(&self) -> Vec3 {
+        self.query.point()
+    }
+    pub fn radius(&self) -> f32 {
+        self.query.radius
+    }
+    pub fn time(&self) -> f32 {
+        self.query.time
+    }
+    /// Shrink the query radius to cull primitives further away, e.g.
+    /// to the distance of the closest point found so far.
+    pub fn set_radius(&mut self, radius: f32) {
+        self.query.radius = radius;
+    }
+    pub fn similarity_scale(&self) -> f32 {
+        self.similarity_scale
+    }
+    /// Get the number of instances the query is currently inside of
+    pub fn instance_stack_size(&self) -> usize {
+        self.context.instStackSize as usize
+    }
+    /// Get the ID of the instance at `level` of the instance stack
+    pub fn instance_id(&self, level: usize) -> u32 {
+        self.context.instID[level]
+    }
+    /// Get the transform from the world to the instance at `level`
+    /// of the instance stack
+    pub fn world_to_instance(&self, level: usize) -> Mat4 {
+        Mat4::from(self.context.world2inst[level])
+    }
+    /// Get the transform from the instance at `level` of the instance
+    /// stack to the world
+    pub fn instance_to_world(&self, level: usize) -> Mat4 {
+        Mat4::from(self.context.inst2world[level])
+    }
+}
+
+/// The closest point to a query point found by `CommittedScene::closest_point`
+#[derive(Debug, Copy, Clone)]
+pub struct ClosestPoint {
+    /// The closest point in world space
+    pub point: Vec3,
+    pub distance: f32,
+    pub geom_id: u32,
+    pub prim_id: u32,
+    /// The ID of the innermost instance the closest primitive is in, in
+    /// the scene of its parent instance, or `u32::MAX`
+    pub inst_id: u32,
+}
+
+/// Compute the closest point to `p` on the triangle `a, b, c`, following
+/// "Real-Time Collision Detection" by Christer Ericson.
+pub fn closest_point_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return a + v * ab;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let v = d2 / (d2 - d6);
+        return a + v * ac;
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let v = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return b + v * (c - b);
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    a + v * ab + w * ac
+}
+
+/// Compute the closest point to `p` on the quad `a, b, c, d`, which
+/// is treated as the two triangles `a, b, d` and `b, c, d` like Embree does.
+pub fn closest_point_quad(p: Vec3, a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> Vec3 {
+    let p0 = closest_point_triangle(p, a, b, d);
+    let p1 = closest_point_triangle(p, b, c, d);
+    if (p0 - p).mag_sq() <= (p1 - p).mag_sq() {
+        p0
+    } else {
+        p1
+    }
+}
+
+pub(crate) type PointQueryFn<'a> = dyn FnMut(&mut PointQueryArgs) + 'a;
+
+pub(crate) unsafe extern "C" fn point_query_function(
+    args: *mut sys::RTCPointQueryFunctionArguments,
+) -> bool {
+    let args = &mut *args;
+    let callback = &mut *(args.userPtr as *mut &mut PointQueryFn);
+    let query = &mut *args.query;
+    let radius = query.radius;
+    let mut query_args = PointQueryArgs {
+        query,
+        prim_id: args.primID,
+        geom_id: args.geomID,
+        context: &*args.context,
+        similarity_scale: args.similarityScale,
+    };
+    catch_unwind_abort(|| callback(&mut query_args));
+    // Tell Embree if the radius was shrunk so it can cull the remaining traversal
+    query_args.radius() != radius
+}
+
+pub(crate) fn user_ptr(callback: &mut &mut PointQueryFn) -> *mut raw::c_void {
+    callback as *mut &mut PointQueryFn as *mut raw::c_void
+}
+
+#[test]
+fn test_closest_point_triangle() {
+    let a = Vec3::new(0.0, 0.0, 0.0);
+    let b = Vec3::new(1.0, 0.0, 0.0);
+    let c = Vec3::new(0.0, 1.0, 0.0);
+    // Inside the face
+    let p = closest_point_triangle(Vec3::new(0.25, 0.25, 1.0), a, b, c);
+    assert_eq!(p, Vec3::new(0.25, 0.25, 0.0));
+    // Closest to a vertex
+    let p = closest_point_triangle(Vec3::new(-1.0, -1.0, 0.0), a, b, c);
+    assert_eq!(p, a);
+    // Closest to the hypotenuse edge
+    let p = closest_point_triangle(Vec3::new(1.0, 1.0, 0.0), a, b, c);
+    assert_eq!(p, Vec3::new(0.5, 0.5, 0.0));
+}
@@ -0,0 +1,194 @@
+use std::ptr;
+
+use ultraviolet::{Vec3, Vec4};
+
+use crate::buffer::{update_all, Buffer};
+use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, new_geometry,
+};
+use crate::sys::*;
+use crate::vertex_attribute::VertexAttribute;
+use crate::{BufferType, Error, Format, GeometryType};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PointType {
+    Sphere,
+    /// Discs which always face the ray
+    Disc,
+    /// Discs facing along the normal of each point
+    OrientedDisc,
+}
+
+pub struct Points<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) handle: RTCGeometry,
+    /// The position and radius of each point
+    pub vertex_buffer: Buffer<'a, Vec4>,
+    /// The vertex buffers for the time steps after the first of animated points
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec4>>,
+    /// The normals of oriented discs
+    pub normal_buffer: Option<Buffer<'a, Vec3>>,
+    pub motion_normal_buffers: Vec<Buffer<'a, Vec3>>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
+}
+
+impl<'a> Points<'a> {
+    pub fn spheres(device: &'a Device, num_points: usize) -> Points<'a> {
+        Points::try_spheres(device, num_points).expect("Failed to create Points")
+    }
+    pub fn try_spheres(device: &'a Device, num_points: usize) -> Result<Points<'a>> {
+        Points::try_animated(device, num_points, PointType::Sphere, 1)
+    }
+    pub fn discs(device: &'a Device, num_points: usize) -> Points<'a> {
+        Points::try_discs(device, num_points).expect("Failed to create Points")
+    }
+    pub fn try_discs(device: &'a Device, num_points: usize) -> Result<Points<'a>> {
+        Points::try_animated(device, num_points, PointType::Disc, 1)
+    }
+    pub fn oriented_discs(device: &'a Device, num_points: usize) -> Points<'a> {
+        Points::try_oriented_discs(device, num_points).expect("Failed to create Points")
+    }
+    pub fn try_oriented_discs(device: &'a Device, num_points: usize) -> Result<Points<'a>> {
+        Points::try_animated(device, num_points, PointType::OrientedDisc, 1)
+    }
+    /// Create points with `time_steps` vertex buffers for motion blur,
+    /// panics if the points can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
+        device: &'a Device,
+        num_points: usize,
+        point_type: PointType,
+        time_steps: u32,
+    ) -> Points<'a> {
+        Points::try_animated(device, num_points, point_type, time_steps)
+            .expect("Failed to create Points")
+    }
+    /// Create points with `time_steps` vertex buffers (and normal buffers
+    /// for oriented discs) for motion blur. The buffers of the first time
+    /// step are in `vertex_buffer` and `normal_buffer` and the following
+    /// ones in `motion_vertex_buffers` and `motion_normal_buffers`.
+    pub fn try_animated(
+        device: &'a Device,
+        num_points: usize,
+        point_type: PointType,
+        time_steps: u32,
+    ) -> Result<Points<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_points)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_points))
+            .collect::<Result<Vec<_>>>()?;
+        let oriented = point_type == PointType::OrientedDisc;
+        let normal_buffer = if oriented {
+            Some(Buffer::try_new(device, num_points)?)
+        } else {
+            None
+        };
+        let motion_normal_buffers = if oriented {
+            (1..time_steps)
+                .map(|_| Buffer::try_new(device, num_points))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        Points {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers,
+            normal_buffer,
+            motion_normal_buffers,
+            vertex_attributes: Vec::new(),
+        }
+        .create(point_type)
+    }
+    /// Create points of `point_type` using existing buffers, e.g. buffers
+    /// sharing the user's memory made with `Buffer::shared` or views of
+    /// a larger buffer made with `Buffer::view`. Oriented discs need a
+    /// normal buffer and spheres and discs can't have one, otherwise an
+    /// `INVALID_ARGUMENT` error is returned.
+    pub fn from_buffers(
+        device: &'a Device,
+        point_type: PointType,
+        vertex_buffer: Buffer<'a, Vec4>,
+        normal_buffer: Option<Buffer<'a, Vec3>>,
+    ) -> Result<Points<'a>> {
+        let oriented = point_type == PointType::OrientedDisc;
+        if oriented != normal_buffer.is_some() {
+            return Err(EmbreeError::new(
+                Error::INVALID_ARGUMENT,
+                &format!(
+                    "{:?} points {} a normal buffer",
+                    point_type,
+                    if oriented { "need" } else { "can't have" }
+                ),
+            ));
+        }
+        Points {
+            device,
+            handle: ptr::null_mut(),
+            vertex_buffer,
+            motion_vertex_buffers: Vec::new(),
+            normal_buffer,
+            motion_normal_buffers: Vec::new(),
+            vertex_attributes: Vec::new(),
+        }
+        .create(point_type)
+    }
+    /// Create the Embree geometry of `point_type` for the points' buffers
+    /// and attach them to it
+    fn create(mut self, point_type: PointType) -> Result<Points<'a>> {
+        let geometry_type = match point_type {
+            PointType::Sphere => GeometryType::SPHERE_POINT,
+            PointType::Disc => GeometryType::DISC_POINT,
+            PointType::OrientedDisc => GeometryType::ORIENTED_DISC_POINT,
+        };
+        let h = unsafe { new_geometry(self.device, geometry_type)? };
+        let num_points = self.vertex_buffer.len();
+        let time_steps = self.motion_vertex_buffers.len() as u32 + 1;
+        unsafe {
+            attach_buffer(
+                h,
+                &mut self.vertex_buffer,
+                BufferType::VERTEX,
+                0,
+                Format::FLOAT4,
+            );
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
+                h,
+                &mut self.motion_vertex_buffers,
+                BufferType::VERTEX,
+                Format::FLOAT4,
+                num_points,
+            );
+            if let Some(ref mut normal_buffer) = self.normal_buffer {
+                attach_buffer(h, normal_buffer, BufferType::NORMAL, 0, Format::FLOAT3);
+                attach_time_step_buffers(
+                    h,
+                    &mut self.motion_normal_buffers,
+                    BufferType::NORMAL,
+                    Format::FLOAT3,
+                    num_points,
+                );
+            }
+            check_new_geometry(self.device, h)?;
+        }
+        self.handle = h;
+        Ok(self)
+    }
+    /// Update the buffers changed since the points was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | update_all(&self.normal_buffer)
+            | update_all(&self.motion_normal_buffers)
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
+    }
+}
+
+unsafe impl<'a> Sync for Points<'a> {}
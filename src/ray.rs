@@ -2,6 +2,7 @@ use std::{f32, u32};
 use ultraviolet::Vec3;
 
 use crate::sys;
+use crate::MAX_INSTANCE_LEVEL_COUNT;
 
 pub type Ray = sys::RTCRay;
 pub type Hit = sys::RTCHit;
@@ -41,12 +42,18 @@ impl Hit {
             v: 0.0,
             primID: u32::MAX,
             geomID: u32::MAX,
-            instID: [u32::MAX; 1],
+            instID: [u32::MAX; MAX_INSTANCE_LEVEL_COUNT],
         }
     }
     pub fn hit(&self) -> bool {
         self.geomID != u32::MAX
     }
+    /// Get the IDs of the instances the hit is nested in, from the
+    /// outermost instance inwards.
+    pub fn instance_ids(&self) -> &[u32] {
+        let depth = self.instID.iter().take_while(|&&id| id != u32::MAX).count();
+        &self.instID[..depth]
+    }
 }
 
 impl RayHit {
@@ -69,7 +76,9 @@ impl IntersectContext {
         sys::RTCIntersectContext {
             flags: flags,
             filter: None,
-            instID: [u32::MAX; 1],
+            #[cfg(feature = "multi-level-instancing")]
+            instStackSize: 0,
+            instID: [u32::MAX; MAX_INSTANCE_LEVEL_COUNT],
         }
     }
 }
@@ -1,192 +1,227 @@
-use std::marker::PhantomData;
 use std::{f32, u32};
 use ultraviolet::Vec3;
 
-use crate::soa_ray::{
-    SoAHit, SoAHitIter, SoAHitIterMut, SoAHitRef, SoARay, SoARayIter, SoARayIterMut, SoARayRef,
-    SoARayRefMut,
-};
+use crate::soa_ray::{SoAHit, SoAHitIter, SoAHitRef, SoARay, SoARayIter, SoARayIterMut};
 use crate::sys;
+use crate::MAX_INSTANCE_LEVEL_COUNT;
 
 pub type Ray4 = sys::RTCRay4;
 pub type Hit4 = sys::RTCHit4;
 pub type RayHit4 = sys::RTCRayHit4;
 
-impl Ray4 {
-    pub fn empty() -> Ray4 {
-        Ray4::segment(
-            [Vec3::new(0.0, 0.0, 0.0); 4],
-            [Vec3::new(0.0, 0.0, 0.0); 4],
-            [0.0; 4],
-            [f32::INFINITY; 4],
-        )
-    }
-    pub fn new(origin: [Vec3; 4], dir: [Vec3; 4]) -> Ray4 {
-        Ray4::segment(origin, dir, [0.0; 4], [f32::INFINITY; 4])
-    }
-    pub fn segment(origin: [Vec3; 4], dir: [Vec3; 4], tnear: [f32; 4], tfar: [f32; 4]) -> Ray4 {
-        sys::RTCRay4 {
-            org_x: [origin[0].x, origin[1].x, origin[2].x, origin[3].x],
-            org_y: [origin[0].y, origin[1].y, origin[2].y, origin[3].y],
-            org_z: [origin[0].z, origin[1].z, origin[2].z, origin[3].z],
-            dir_x: [dir[0].x, dir[1].x, dir[2].x, dir[3].x],
-            dir_y: [dir[0].y, dir[1].y, dir[2].y, dir[3].y],
-            dir_z: [dir[0].z, dir[1].z, dir[2].z, dir[3].z],
-            tnear: tnear,
-            tfar: tfar,
-            time: [0.0; 4],
-            mask: [u32::MAX; 4],
-            id: [0; 4],
-            flags: [0; 4],
+pub type Ray8 = sys::RTCRay8;
+pub type Hit8 = sys::RTCHit8;
+pub type RayHit8 = sys::RTCRayHit8;
+
+pub type Ray16 = sys::RTCRay16;
+pub type Hit16 = sys::RTCHit16;
+pub type RayHit16 = sys::RTCRayHit16;
+
+/// Implement the constructors and SoA ray/hit traits for the ray packet
+/// types of width `$n`. The Embree packet types only differ in their width
+/// so the same code is shared across all of them.
+macro_rules! impl_ray_packet {
+    (
+        $ray:ident,
+        $hit:ident,
+        $rayhit:ident,
+        $sys_ray:ident,
+        $sys_hit:ident,
+        $sys_rayhit:ident,
+        $n:expr
+    ) => {
+        impl $ray {
+            pub fn empty() -> $ray {
+                $ray::segment(
+                    [Vec3::new(0.0, 0.0, 0.0); $n],
+                    [Vec3::new(0.0, 0.0, 0.0); $n],
+                    [0.0; $n],
+                    [f32::INFINITY; $n],
+                )
+            }
+            pub fn new(origin: [Vec3; $n], dir: [Vec3; $n]) -> $ray {
+                $ray::segment(origin, dir, [0.0; $n], [f32::INFINITY; $n])
+            }
+            pub fn segment(
+                origin: [Vec3; $n],
+                dir: [Vec3; $n],
+                tnear: [f32; $n],
+                tfar: [f32; $n],
+            ) -> $ray {
+                sys::$sys_ray {
+                    org_x: origin.map(|o| o.x),
+                    org_y: origin.map(|o| o.y),
+                    org_z: origin.map(|o| o.z),
+                    dir_x: dir.map(|d| d.x),
+                    dir_y: dir.map(|d| d.y),
+                    dir_z: dir.map(|d| d.z),
+                    tnear,
+                    tfar,
+                    time: [0.0; $n],
+                    mask: [u32::MAX; $n],
+                    id: [0; $n],
+                    flags: [0; $n],
+                }
+            }
+            pub fn iter(&self) -> SoARayIter<'_, $ray> {
+                SoARayIter::new(self, $n)
+            }
+            pub fn iter_mut(&mut self) -> SoARayIterMut<'_, $ray> {
+                SoARayIterMut::new(self, $n)
+            }
         }
-    }
-    pub fn iter(&self) -> SoARayIter<Ray4> {
-        SoARayIter::new(self, 4)
-    }
-    pub fn iter_mut(&mut self) -> SoARayIterMut<Ray4> {
-        SoARayIterMut::new(self, 4)
-    }
-}
 
-impl SoARay for Ray4 {
-    fn org(&self, i: usize) -> Vec3 {
-        Vec3::new(self.org_x[i], self.org_y[i], self.org_z[i])
-    }
-    fn set_org(&mut self, i: usize, o: Vec3) {
-        self.org_x[i] = o.x;
-        self.org_y[i] = o.y;
-        self.org_z[i] = o.z;
-    }
-
-    fn dir(&self, i: usize) -> Vec3 {
-        Vec3::new(self.dir_x[i], self.dir_y[i], self.dir_z[i])
-    }
-    fn set_dir(&mut self, i: usize, d: Vec3) {
-        self.dir_x[i] = d.x;
-        self.dir_y[i] = d.y;
-        self.dir_z[i] = d.z;
-    }
-
-    fn tnear(&self, i: usize) -> f32 {
-        self.tnear[i]
-    }
-    fn set_tnear(&mut self, i: usize, near: f32) {
-        self.tnear[i] = near;
-    }
-
-    fn tfar(&self, i: usize) -> f32 {
-        self.tfar[i]
-    }
-    fn set_tfar(&mut self, i: usize, far: f32) {
-        self.tfar[i] = far;
-    }
-
-    fn time(&self, i: usize) -> f32 {
-        self.time[i]
-    }
-    fn set_time(&mut self, i: usize, time: f32) {
-        self.time[i] = time;
-    }
-
-    fn mask(&self, i: usize) -> u32 {
-        self.mask[i]
-    }
-    fn set_mask(&mut self, i: usize, mask: u32) {
-        self.mask[i] = mask;
-    }
-
-    fn id(&self, i: usize) -> u32 {
-        self.id[i]
-    }
-    fn set_id(&mut self, i: usize, id: u32) {
-        self.id[i] = id;
-    }
-
-    fn flags(&self, i: usize) -> u32 {
-        self.flags[i]
-    }
-    fn set_flags(&mut self, i: usize, flags: u32) {
-        self.flags[i] = flags;
-    }
-}
+        impl SoARay for $ray {
+            fn org(&self, i: usize) -> Vec3 {
+                Vec3::new(self.org_x[i], self.org_y[i], self.org_z[i])
+            }
+            fn set_org(&mut self, i: usize, o: Vec3) {
+                self.org_x[i] = o.x;
+                self.org_y[i] = o.y;
+                self.org_z[i] = o.z;
+            }
+
+            fn dir(&self, i: usize) -> Vec3 {
+                Vec3::new(self.dir_x[i], self.dir_y[i], self.dir_z[i])
+            }
+            fn set_dir(&mut self, i: usize, d: Vec3) {
+                self.dir_x[i] = d.x;
+                self.dir_y[i] = d.y;
+                self.dir_z[i] = d.z;
+            }
+
+            fn tnear(&self, i: usize) -> f32 {
+                self.tnear[i]
+            }
+            fn set_tnear(&mut self, i: usize, near: f32) {
+                self.tnear[i] = near;
+            }
+
+            fn tfar(&self, i: usize) -> f32 {
+                self.tfar[i]
+            }
+            fn set_tfar(&mut self, i: usize, far: f32) {
+                self.tfar[i] = far;
+            }
+
+            fn time(&self, i: usize) -> f32 {
+                self.time[i]
+            }
+            fn set_time(&mut self, i: usize, time: f32) {
+                self.time[i] = time;
+            }
+
+            fn mask(&self, i: usize) -> u32 {
+                self.mask[i]
+            }
+            fn set_mask(&mut self, i: usize, mask: u32) {
+                self.mask[i] = mask;
+            }
+
+            fn id(&self, i: usize) -> u32 {
+                self.id[i]
+            }
+            fn set_id(&mut self, i: usize, id: u32) {
+                self.id[i] = id;
+            }
+
+            fn flags(&self, i: usize) -> u32 {
+                self.flags[i]
+            }
+            fn set_flags(&mut self, i: usize, flags: u32) {
+                self.flags[i] = flags;
+            }
+        }
 
-impl Hit4 {
-    pub fn new() -> Hit4 {
-        sys::RTCHit4 {
-            Ng_x: [0.0; 4],
-            Ng_y: [0.0; 4],
-            Ng_z: [0.0; 4],
-            u: [0.0; 4],
-            v: [0.0; 4],
-            primID: [u32::MAX; 4],
-            geomID: [u32::MAX; 4],
-            instID: [[u32::MAX; 4]],
+        impl $hit {
+            pub fn new() -> $hit {
+                sys::$sys_hit {
+                    Ng_x: [0.0; $n],
+                    Ng_y: [0.0; $n],
+                    Ng_z: [0.0; $n],
+                    u: [0.0; $n],
+                    v: [0.0; $n],
+                    primID: [u32::MAX; $n],
+                    geomID: [u32::MAX; $n],
+                    instID: [[u32::MAX; $n]; MAX_INSTANCE_LEVEL_COUNT],
+                }
+            }
+            pub fn any_hit(&self) -> bool {
+                self.hits().fold(false, |acc, g| acc || g)
+            }
+            pub fn hits<'a>(&'a self) -> impl Iterator<Item = bool> + 'a {
+                self.geomID.iter().map(|g| *g != u32::MAX)
+            }
+            pub fn iter(&self) -> SoAHitIter<'_, $hit> {
+                SoAHitIter::new(self, $n)
+            }
+            pub fn iter_hits<'a>(&'a self) -> impl Iterator<Item = SoAHitRef<'a, $hit>> + 'a {
+                SoAHitIter::new(self, $n).filter(|h| h.hit())
+            }
         }
-    }
-    pub fn any_hit(&self) -> bool {
-        self.hits().fold(false, |acc, g| acc || g)
-    }
-    pub fn hits<'a>(&'a self) -> impl Iterator<Item = bool> + 'a {
-        self.geomID.iter().map(|g| *g != u32::MAX)
-    }
-    pub fn iter(&self) -> SoAHitIter<Hit4> {
-        SoAHitIter::new(self, 4)
-    }
-    pub fn iter_hits<'a>(&'a self) -> impl Iterator<Item = SoAHitRef<Hit4>> + 'a {
-        SoAHitIter::new(self, 4).filter(|h| h.hit())
-    }
-}
 
-impl SoAHit for Hit4 {
-    fn normal(&self, i: usize) -> Vec3 {
-        Vec3::new(self.Ng_x[i], self.Ng_y[i], self.Ng_z[i])
-    }
-    fn set_normal(&mut self, i: usize, n: Vec3) {
-        self.Ng_x[i] = n.x;
-        self.Ng_y[i] = n.y;
-        self.Ng_z[i] = n.z;
-    }
-
-    fn uv(&self, i: usize) -> (f32, f32) {
-        (self.u[i], self.v[i])
-    }
-    fn set_u(&mut self, i: usize, u: f32) {
-        self.u[i] = u;
-    }
-    fn set_v(&mut self, i: usize, v: f32) {
-        self.v[i] = v;
-    }
-
-    fn prim_id(&self, i: usize) -> u32 {
-        self.primID[i]
-    }
-    fn set_prim_id(&mut self, i: usize, id: u32) {
-        self.primID[i] = id;
-    }
-
-    fn geom_id(&self, i: usize) -> u32 {
-        self.geomID[i]
-    }
-    fn set_geom_id(&mut self, i: usize, id: u32) {
-        self.geomID[i] = id;
-    }
-
-    fn inst_id(&self, i: usize) -> u32 {
-        self.instID[0][i]
-    }
-    fn set_inst_id(&mut self, i: usize, id: u32) {
-        self.instID[0][i] = id;
-    }
-}
+        impl Default for $hit {
+            fn default() -> $hit {
+                $hit::new()
+            }
+        }
+
+        impl SoAHit for $hit {
+            fn normal(&self, i: usize) -> Vec3 {
+                Vec3::new(self.Ng_x[i], self.Ng_y[i], self.Ng_z[i])
+            }
+            fn set_normal(&mut self, i: usize, n: Vec3) {
+                self.Ng_x[i] = n.x;
+                self.Ng_y[i] = n.y;
+                self.Ng_z[i] = n.z;
+            }
+
+            fn uv(&self, i: usize) -> (f32, f32) {
+                (self.u[i], self.v[i])
+            }
+            fn set_u(&mut self, i: usize, u: f32) {
+                self.u[i] = u;
+            }
+            fn set_v(&mut self, i: usize, v: f32) {
+                self.v[i] = v;
+            }
+
+            fn prim_id(&self, i: usize) -> u32 {
+                self.primID[i]
+            }
+            fn set_prim_id(&mut self, i: usize, id: u32) {
+                self.primID[i] = id;
+            }
+
+            fn geom_id(&self, i: usize) -> u32 {
+                self.geomID[i]
+            }
+            fn set_geom_id(&mut self, i: usize, id: u32) {
+                self.geomID[i] = id;
+            }
+
+            fn inst_id_at(&self, i: usize, level: usize) -> u32 {
+                self.instID[level][i]
+            }
+            fn set_inst_id_at(&mut self, i: usize, level: usize, id: u32) {
+                self.instID[level][i] = id;
+            }
+        }
 
-impl RayHit4 {
-    pub fn new(ray: Ray4) -> RayHit4 {
-        sys::RTCRayHit4 {
-            ray: ray,
-            hit: Hit4::new(),
+        impl $rayhit {
+            pub fn new(ray: $ray) -> $rayhit {
+                sys::$sys_rayhit {
+                    ray,
+                    hit: $hit::new(),
+                }
+            }
+            pub fn iter(&self) -> std::iter::Zip<SoARayIter<'_, $ray>, SoAHitIter<'_, $hit>> {
+                self.ray.iter().zip(self.hit.iter())
+            }
         }
-    }
-    pub fn iter(&self) -> std::iter::Zip<SoARayIter<Ray4>, SoAHitIter<Hit4>> {
-        self.ray.iter().zip(self.hit.iter())
-    }
+    };
 }
+
+impl_ray_packet!(Ray4, Hit4, RayHit4, RTCRay4, RTCHit4, RTCRayHit4, 4);
+impl_ray_packet!(Ray8, Hit8, RayHit8, RTCRay8, RTCHit8, RTCRayHit8, 8);
+impl_ray_packet!(Ray16, Hit16, RayHit16, RTCRay16, RTCHit16, RTCRayHit16, 16);
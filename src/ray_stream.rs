@@ -1,5 +1,6 @@
 use std::iter::Iterator;
 use std::marker::PhantomData;
+use std::ptr;
 use std::{f32, u32};
 use ultraviolet::Vec3;
 
@@ -8,7 +9,7 @@ use crate::soa_ray::{
     SoARayRefMut,
 };
 use crate::sys;
-use crate::{aligned_vector, aligned_vector_init};
+use crate::{aligned_vector, aligned_vector_init, MAX_INSTANCE_LEVEL_COUNT};
 
 /// A ray stream stored in SoA format
 pub struct RayN {
@@ -142,7 +143,8 @@ pub struct HitN {
     v: Vec<f32>,
     prim_id: Vec<::std::os::raw::c_uint>,
     geom_id: Vec<::std::os::raw::c_uint>,
-    inst_id: Vec<::std::os::raw::c_uint>,
+    /// The instance ID stack, one vector per level
+    inst_id: Vec<Vec<::std::os::raw::c_uint>>,
 }
 
 impl HitN {
@@ -155,7 +157,9 @@ impl HitN {
             v: aligned_vector::<f32>(n, 16),
             prim_id: aligned_vector_init::<u32>(n, 16, u32::MAX),
             geom_id: aligned_vector_init::<u32>(n, 16, u32::MAX),
-            inst_id: aligned_vector_init::<u32>(n, 16, u32::MAX),
+            inst_id: (0..MAX_INSTANCE_LEVEL_COUNT)
+                .map(|_| aligned_vector_init::<u32>(n, 16, u32::MAX))
+                .collect(),
         }
     }
     pub fn any_hit(&self) -> bool {
@@ -174,6 +178,10 @@ impl HitN {
         self.ng_x.len()
     }
     pub unsafe fn as_hitnp(&mut self) -> sys::RTCHitNp {
+        let mut inst_id = [ptr::null_mut(); MAX_INSTANCE_LEVEL_COUNT];
+        for (p, ids) in inst_id.iter_mut().zip(self.inst_id.iter_mut()) {
+            *p = ids.as_mut_ptr();
+        }
         sys::RTCHitNp {
             Ng_x: self.ng_x.as_mut_ptr(),
             Ng_y: self.ng_y.as_mut_ptr(),
@@ -182,7 +190,7 @@ impl HitN {
             v: self.v.as_mut_ptr(),
             primID: self.prim_id.as_mut_ptr(),
             geomID: self.geom_id.as_mut_ptr(),
-            instID: [self.inst_id.as_mut_ptr(); 1usize],
+            instID: inst_id,
         }
     }
 }
@@ -221,11 +229,11 @@ impl SoAHit for HitN {
         self.geom_id[i] = id;
     }
 
-    fn inst_id(&self, i: usize) -> u32 {
-        self.inst_id[i]
+    fn inst_id_at(&self, i: usize, level: usize) -> u32 {
+        self.inst_id[level][i]
     }
-    fn set_inst_id(&mut self, i: usize, id: u32) {
-        self.inst_id[i] = id;
+    fn set_inst_id_at(&mut self, i: usize, level: usize, id: u32) {
+        self.inst_id[level][i] = id;
     }
 }
 
@@ -255,3 +263,208 @@ impl RayHitN {
         }
     }
 }
+
+/// A view of the SoA `RTCRayN` packet of width `n` which Embree passes
+/// to user geometry and filter callbacks.
+pub struct RayNRef<'a> {
+    ptr: *mut u32,
+    n: usize,
+    marker: PhantomData<&'a mut sys::RTCRayN>,
+}
+
+impl<'a> RayNRef<'a> {
+    pub(crate) unsafe fn from_raw(ray: *mut sys::RTCRayN, n: usize) -> RayNRef<'a> {
+        RayNRef {
+            ptr: ray as *mut u32,
+            n,
+            marker: PhantomData,
+        }
+    }
+    pub fn iter(&self) -> SoARayIter<'_, RayNRef<'a>> {
+        SoARayIter::new(self, self.n)
+    }
+    pub fn iter_mut(&mut self) -> SoARayIterMut<'_, RayNRef<'a>> {
+        let n = self.n;
+        SoARayIterMut::new(self, n)
+    }
+    pub fn len(&self) -> usize {
+        self.n
+    }
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+    fn get_f32(&self, field: usize, i: usize) -> f32 {
+        f32::from_bits(self.get_u32(field, i))
+    }
+    fn set_f32(&mut self, field: usize, i: usize, x: f32) {
+        self.set_u32(field, i, x.to_bits());
+    }
+    fn get_u32(&self, field: usize, i: usize) -> u32 {
+        assert!(i < self.n);
+        unsafe { *self.ptr.add(field * self.n + i) }
+    }
+    fn set_u32(&mut self, field: usize, i: usize, x: u32) {
+        assert!(i < self.n);
+        unsafe {
+            *self.ptr.add(field * self.n + i) = x;
+        }
+    }
+}
+
+impl<'a> SoARay for RayNRef<'a> {
+    fn org(&self, i: usize) -> Vec3 {
+        Vec3::new(self.get_f32(0, i), self.get_f32(1, i), self.get_f32(2, i))
+    }
+    fn set_org(&mut self, i: usize, o: Vec3) {
+        self.set_f32(0, i, o.x);
+        self.set_f32(1, i, o.y);
+        self.set_f32(2, i, o.z);
+    }
+
+    fn dir(&self, i: usize) -> Vec3 {
+        Vec3::new(self.get_f32(4, i), self.get_f32(5, i), self.get_f32(6, i))
+    }
+    fn set_dir(&mut self, i: usize, d: Vec3) {
+        self.set_f32(4, i, d.x);
+        self.set_f32(5, i, d.y);
+        self.set_f32(6, i, d.z);
+    }
+
+    fn tnear(&self, i: usize) -> f32 {
+        self.get_f32(3, i)
+    }
+    fn set_tnear(&mut self, i: usize, near: f32) {
+        self.set_f32(3, i, near);
+    }
+
+    fn tfar(&self, i: usize) -> f32 {
+        self.get_f32(8, i)
+    }
+    fn set_tfar(&mut self, i: usize, far: f32) {
+        self.set_f32(8, i, far);
+    }
+
+    fn time(&self, i: usize) -> f32 {
+        self.get_f32(7, i)
+    }
+    fn set_time(&mut self, i: usize, time: f32) {
+        self.set_f32(7, i, time);
+    }
+
+    fn mask(&self, i: usize) -> u32 {
+        self.get_u32(9, i)
+    }
+    fn set_mask(&mut self, i: usize, mask: u32) {
+        self.set_u32(9, i, mask);
+    }
+
+    fn id(&self, i: usize) -> u32 {
+        self.get_u32(10, i)
+    }
+    fn set_id(&mut self, i: usize, id: u32) {
+        self.set_u32(10, i, id);
+    }
+
+    fn flags(&self, i: usize) -> u32 {
+        self.get_u32(11, i)
+    }
+    fn set_flags(&mut self, i: usize, flags: u32) {
+        self.set_u32(11, i, flags);
+    }
+}
+
+/// A view of the SoA `RTCHitN` packet of width `n` which Embree passes
+/// to user geometry and filter callbacks.
+pub struct HitNRef<'a> {
+    ptr: *mut u32,
+    n: usize,
+    marker: PhantomData<&'a mut sys::RTCHitN>,
+}
+
+impl<'a> HitNRef<'a> {
+    pub(crate) unsafe fn from_raw(hit: *mut sys::RTCHitN, n: usize) -> HitNRef<'a> {
+        HitNRef {
+            ptr: hit as *mut u32,
+            n,
+            marker: PhantomData,
+        }
+    }
+    /// Get the hit part of an `RTCRayHitN`, which is stored after the 12
+    /// SoA ray members.
+    pub(crate) unsafe fn from_rayhit(rayhit: *mut sys::RTCRayHitN, n: usize) -> HitNRef<'a> {
+        HitNRef::from_raw((rayhit as *mut u32).add(12 * n) as *mut sys::RTCHitN, n)
+    }
+    pub fn iter(&self) -> SoAHitIter<'_, HitNRef<'a>> {
+        SoAHitIter::new(self, self.n)
+    }
+    pub fn iter_mut(&mut self) -> SoAHitIterMut<'_, HitNRef<'a>> {
+        let n = self.n;
+        SoAHitIterMut::new(self, n)
+    }
+    pub fn len(&self) -> usize {
+        self.n
+    }
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+    fn get_f32(&self, field: usize, i: usize) -> f32 {
+        f32::from_bits(self.get_u32(field, i))
+    }
+    fn set_f32(&mut self, field: usize, i: usize, x: f32) {
+        self.set_u32(field, i, x.to_bits());
+    }
+    fn get_u32(&self, field: usize, i: usize) -> u32 {
+        assert!(i < self.n);
+        unsafe { *self.ptr.add(field * self.n + i) }
+    }
+    fn set_u32(&mut self, field: usize, i: usize, x: u32) {
+        assert!(i < self.n);
+        unsafe {
+            *self.ptr.add(field * self.n + i) = x;
+        }
+    }
+}
+
+impl<'a> SoAHit for HitNRef<'a> {
+    fn normal(&self, i: usize) -> Vec3 {
+        Vec3::new(self.get_f32(0, i), self.get_f32(1, i), self.get_f32(2, i))
+    }
+    fn set_normal(&mut self, i: usize, n: Vec3) {
+        self.set_f32(0, i, n.x);
+        self.set_f32(1, i, n.y);
+        self.set_f32(2, i, n.z);
+    }
+
+    fn uv(&self, i: usize) -> (f32, f32) {
+        (self.get_f32(3, i), self.get_f32(4, i))
+    }
+    fn set_u(&mut self, i: usize, u: f32) {
+        self.set_f32(3, i, u);
+    }
+    fn set_v(&mut self, i: usize, v: f32) {
+        self.set_f32(4, i, v);
+    }
+
+    fn prim_id(&self, i: usize) -> u32 {
+        self.get_u32(5, i)
+    }
+    fn set_prim_id(&mut self, i: usize, id: u32) {
+        self.set_u32(5, i, id);
+    }
+
+    fn geom_id(&self, i: usize) -> u32 {
+        self.get_u32(6, i)
+    }
+    fn set_geom_id(&mut self, i: usize, id: u32) {
+        self.set_u32(6, i, id);
+    }
+
+    fn inst_id_at(&self, i: usize, level: usize) -> u32 {
+        assert!(level < MAX_INSTANCE_LEVEL_COUNT);
+        self.get_u32(7 + level, i)
+    }
+    fn set_inst_id_at(&mut self, i: usize, level: usize, id: u32) {
+        assert!(level < MAX_INSTANCE_LEVEL_COUNT);
+        self.set_u32(7 + level, i, id);
+    }
+}
@@ -1,13 +1,85 @@
 use std::collections::HashMap;
-use std::marker::PhantomData;
 use std::mem;
+use std::os::raw;
 
+use ultraviolet::{Mat4, Vec3};
+
+use crate::bounds::{Bounds, LinearBounds};
+use crate::collide::{self, CollideFn, Collision};
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::error::{EmbreeError, Result};
+use crate::geometry::{commit_geometry, is_committed, Geometry};
+use crate::point_query::{
+    self, closest_point_quad, closest_point_triangle, ClosestPoint, PointQuery, PointQueryArgs,
+    PointQueryContext, PointQueryFn,
+};
 use crate::ray::{IntersectContext, Ray, RayHit};
-use crate::ray_packet::{Ray4, RayHit4};
+use crate::ray_packet::{Ray16, Ray4, Ray8, RayHit16, RayHit4, RayHit8};
 use crate::ray_stream::{RayHitN, RayN};
 use crate::sys::*;
+use crate::{BuildQuality, Error, SceneFlags};
+
+/// The flags and BVH build quality to create a scene with, see
+/// `Scene::with_options`. Defaults to Embree's defaults of no flags
+/// and `MEDIUM` build quality.
+///
+/// An interactive viewer rebuilding the scene every frame should favor
+/// build speed with `interactive`, while a final frame render should
+/// favor trace speed and precision with `final_frame`.
+///
+/// ```no_run
+/// use embree::{BuildQuality, Device, Scene, SceneFlags, SceneOptions};
+///
+/// let device = Device::new();
+/// let viewer = Scene::with_options(&device, &SceneOptions::interactive()).unwrap();
+/// let options = SceneOptions::final_frame().flags(SceneFlags::ROBUST | SceneFlags::COMPACT);
+/// let render = Scene::with_options(&device, &options).unwrap();
+/// assert_eq!(render.build_quality(), BuildQuality::HIGH);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SceneOptions {
+    flags: SceneFlags,
+    build_quality: BuildQuality,
+}
+
+impl Default for SceneOptions {
+    fn default() -> SceneOptions {
+        SceneOptions {
+            flags: SceneFlags::NONE,
+            build_quality: BuildQuality::MEDIUM,
+        }
+    }
+}
+
+impl SceneOptions {
+    pub fn new() -> SceneOptions {
+        SceneOptions::default()
+    }
+    /// Options for scenes which are changed and committed often, e.g.
+    /// in an interactive viewer: `DYNAMIC` flags and `LOW` build quality
+    /// to minimize the time to rebuild the BVH.
+    pub fn interactive() -> SceneOptions {
+        SceneOptions::new()
+            .flags(SceneFlags::DYNAMIC)
+            .build_quality(BuildQuality::LOW)
+    }
+    /// Options for scenes which are built once and traced heavily, e.g.
+    /// for a final frame: `ROBUST` flags to avoid missing hits at edges
+    /// and `HIGH` build quality for the fastest traversal.
+    pub fn final_frame() -> SceneOptions {
+        SceneOptions::new()
+            .flags(SceneFlags::ROBUST)
+            .build_quality(BuildQuality::HIGH)
+    }
+    pub fn flags(mut self, flags: SceneFlags) -> SceneOptions {
+        self.flags = flags;
+        self
+    }
+    pub fn build_quality(mut self, quality: BuildQuality) -> SceneOptions {
+        self.build_quality = quality;
+        self
+    }
+}
 
 /// A scene containing various geometry for rendering. Geometry
 /// can be added and removed by attaching and detaching it, after
@@ -15,20 +87,61 @@ use crate::sys::*;
 /// return a `CommittedScene` which can be used for ray queries.
 pub struct Scene<'a> {
     pub(crate) handle: RTCScene,
-    /// We don't need to actually keep a reference to the device,
-    /// we just need to track its lifetime for correctness
-    device: PhantomData<&'a Device>,
+    device: &'a Device,
     geometry: HashMap<u32, Geometry<'a>>,
+    build_quality: BuildQuality,
 }
 
 impl<'a> Scene<'a> {
+    /// Create a new scene, panics if the scene can't be created.
+    /// See `try_new` for a fallible version.
     pub fn new(device: &'a Device) -> Scene {
-        Scene {
-            handle: unsafe { rtcNewScene(device.handle) },
-            device: PhantomData,
+        Scene::try_new(device).expect("Failed to create scene")
+    }
+    pub fn try_new(device: &'a Device) -> Result<Scene<'a>> {
+        Scene::with_options(device, &SceneOptions::default())
+    }
+    /// Create a new scene with the flags and build quality in `options`
+    pub fn with_options(device: &'a Device, options: &SceneOptions) -> Result<Scene<'a>> {
+        device.clear_error();
+        let handle = unsafe { rtcNewScene(device.handle) };
+        if handle.is_null() {
+            return Err(device.error_or("Failed to create scene"));
+        }
+        let mut scene = Scene {
+            handle,
+            device,
             geometry: HashMap::new(),
+            build_quality: BuildQuality::MEDIUM,
+        };
+        scene.set_flags(options.flags);
+        scene.set_build_quality(options.build_quality);
+        device.get_error()?;
+        Ok(scene)
+    }
+    /// Set the scene flags, e.g. `SceneFlags::CONTEXT_FILTER_FUNCTION` to
+    /// enable running the filter of a `FilterContext`. The flags take
+    /// effect when the scene is next committed.
+    pub fn set_flags(&mut self, flags: SceneFlags) {
+        unsafe {
+            rtcSetSceneFlags(self.handle, flags);
         }
     }
+    pub fn flags(&self) -> SceneFlags {
+        unsafe { rtcGetSceneFlags(self.handle) }
+    }
+    /// Set the quality of the scene's top level BVH build, the quality
+    /// of each geometry's BVH is set on the geometry. Takes effect when
+    /// the scene is next committed.
+    pub fn set_build_quality(&mut self, quality: BuildQuality) {
+        unsafe {
+            rtcSetSceneBuildQuality(self.handle, quality);
+        }
+        self.build_quality = quality;
+    }
+    pub fn build_quality(&self) -> BuildQuality {
+        self.build_quality
+    }
     /// Attach a new geometry to the scene. Returns the scene local ID which
     /// can than be used to find the hit geometry from the ray ID member.
     /// A geometry can only be attached to one Scene at a time, per the Embree
@@ -69,12 +182,49 @@ impl<'a> Scene<'a> {
     /// Commit the scene to build the BVH on top of the geometry to allow
     /// for ray tracing the scene. The returned `CommittedScene` can be
     /// used for intersection and occlusion tests. The `Scene` can't
-    /// be modified while the `CommittedScene` is active.
-    pub fn commit(&'a self) -> CommittedScene<'a> {
+    /// be modified while the `CommittedScene` is active, once it's dropped
+    /// the scene can be changed and committed again, e.g. each frame.
+    ///
+    /// Geometry whose buffers were written through `Buffer::as_mut_slice`
+    /// or whose settings were changed since it was last committed is
+    /// updated and committed again, as is geometry which was never
+    /// committed. Unchanged geometry is not rebuilt.
+    pub fn commit(&mut self) -> CommittedScene<'_> {
+        self.commit_changed_geometry();
         unsafe {
             rtcCommitScene(self.handle);
         }
-        CommittedScene { scene: &self }
+        CommittedScene { scene: self }
+    }
+    /// Commit the scene, returning an error if Embree reported one
+    /// while building the BVH. Errors from earlier calls on this thread
+    /// which haven't been checked will also be returned.
+    pub fn try_commit(&mut self) -> Result<CommittedScene<'_>> {
+        self.commit_changed_geometry();
+        unsafe {
+            rtcCommitScene(self.handle);
+        }
+        self.device.get_error()?;
+        Ok(CommittedScene { scene: self })
+    }
+    fn commit_changed_geometry(&mut self) {
+        // Buffers may be shared between geometries, so all buffers are
+        // updated before checking which geometries were changed
+        for g in self.geometry.values() {
+            g.update_buffers();
+        }
+        for g in self.geometry.values() {
+            // take_changed is checked first so the flag is always cleared
+            if g.take_changed() || unsafe { !is_committed(g.handle()) } {
+                unsafe {
+                    commit_geometry(g.handle());
+                }
+            }
+        }
+    }
+    /// Get the device the scene was created on
+    pub fn device(&self) -> &'a Device {
+        self.device
     }
     /// Get the underlying handle to the scene, e.g. for passing it to
     /// native code or ISPC kernels.
@@ -138,6 +288,46 @@ impl<'a> CommittedScene<'a> {
             );
         }
     }
+    pub fn intersect8(&self, ctx: &mut IntersectContext, ray: &mut RayHit8, valid: &[i32; 8]) {
+        unsafe {
+            rtcIntersect8(
+                valid.as_ptr(),
+                self.scene.handle,
+                ctx as *mut RTCIntersectContext,
+                ray as *mut RTCRayHit8,
+            );
+        }
+    }
+    pub fn occluded8(&self, ctx: &mut IntersectContext, ray: &mut Ray8, valid: &[i32; 8]) {
+        unsafe {
+            rtcOccluded8(
+                valid.as_ptr(),
+                self.scene.handle,
+                ctx as *mut RTCIntersectContext,
+                ray as *mut RTCRay8,
+            );
+        }
+    }
+    pub fn intersect16(&self, ctx: &mut IntersectContext, ray: &mut RayHit16, valid: &[i32; 16]) {
+        unsafe {
+            rtcIntersect16(
+                valid.as_ptr(),
+                self.scene.handle,
+                ctx as *mut RTCIntersectContext,
+                ray as *mut RTCRayHit16,
+            );
+        }
+    }
+    pub fn occluded16(&self, ctx: &mut IntersectContext, ray: &mut Ray16, valid: &[i32; 16]) {
+        unsafe {
+            rtcOccluded16(
+                valid.as_ptr(),
+                self.scene.handle,
+                ctx as *mut RTCIntersectContext,
+                ray as *mut RTCRay16,
+            );
+        }
+    }
     pub fn intersect_stream_aos(&self, ctx: &mut IntersectContext, rays: &mut Vec<RayHit>) {
         let m = rays.len();
         unsafe {
@@ -186,22 +376,167 @@ impl<'a> CommittedScene<'a> {
             );
         }
     }
-    pub fn bounds(&self) -> RTCBounds {
-        let mut bounds = RTCBounds {
-            lower_x: 0.0,
-            upper_x: 0.0,
-            lower_y: 0.0,
-            upper_y: 0.0,
-            lower_z: 0.0,
-            upper_z: 0.0,
-            align0: 0.0,
-            align1: 0.0,
-        };
+    /// Run a point query to find the primitives within `query.radius` of
+    /// the query point. The callback is called for each primitive whose
+    /// bounds overlap the query sphere, and can shrink the query radius
+    /// with `PointQueryArgs::set_radius` to cull further primitives, e.g.
+    /// when searching for the closest point. Returns true if the radius
+    /// was shrunk.
+    pub fn point_query<F>(&self, query: &mut PointQuery, mut f: F) -> bool
+    where
+        F: FnMut(&mut PointQueryArgs),
+    {
+        let mut context = PointQueryContext::new();
+        let mut callback: &mut PointQueryFn = &mut f;
+        unsafe {
+            rtcPointQuery(
+                self.scene.handle,
+                query as *mut RTCPointQuery,
+                &mut context as *mut RTCPointQueryContext,
+                Some(point_query::point_query_function),
+                point_query::user_ptr(&mut callback),
+            )
+        }
+    }
+    /// Find the closest point to `p` within `radius` on the triangle and
+    /// quad meshes in the scene, including those in instances.
+    pub fn closest_point(&self, p: Vec3, radius: f32) -> Option<ClosestPoint> {
+        let mut closest: Option<ClosestPoint> = None;
+        let mut query = PointQuery::new(p, radius);
+        self.point_query(&mut query, |args| {
+            // Walk down the instance stack to the scene the primitive is in
+            let levels = args.instance_stack_size();
+            let mut scene: &Scene = self.scene;
+            for level in 0..levels {
+                match scene.get_geometry(args.instance_id(level)) {
+                    Some(Geometry::Instance(inst)) => scene = inst.scene.scene,
+                    _ => return,
+                }
+            }
+            let geometry = scene.get_geometry(args.geom_id());
+            let inst_id = if levels > 0 {
+                args.instance_id(levels - 1)
+            } else {
+                u32::MAX
+            };
+            // Find the closest point in world space, the query point may have
+            // been transformed into the instance's space so we use `p` directly.
+            // The transform of the innermost instance includes its parents'.
+            let to_world = |v: Vec3| {
+                if levels == 0 {
+                    v
+                } else {
+                    args.instance_to_world(levels - 1).transform_point3(v)
+                }
+            };
+            let point = match geometry {
+                Some(Geometry::Triangle(mesh)) => {
+                    let v = mesh.triangle(args.prim_id());
+                    closest_point_triangle(p, to_world(v[0]), to_world(v[1]), to_world(v[2]))
+                }
+                Some(Geometry::Quad(mesh)) => {
+                    let v = mesh.quad(args.prim_id());
+                    closest_point_quad(
+                        p,
+                        to_world(v[0]),
+                        to_world(v[1]),
+                        to_world(v[2]),
+                        to_world(v[3]),
+                    )
+                }
+                _ => return,
+            };
+            let distance = (point - p).mag();
+            if distance <= closest.map_or(radius, |c| c.distance) {
+                closest = Some(ClosestPoint {
+                    point,
+                    distance,
+                    geom_id: args.geom_id(),
+                    prim_id: args.prim_id(),
+                    inst_id,
+                });
+                let scale = args.similarity_scale();
+                args.set_radius(if scale > 0.0 {
+                    distance * scale
+                } else {
+                    distance
+                });
+            }
+        });
+        closest
+    }
+    /// Find the pairs of overlapping primitives between this scene and
+    /// `other`. Embree only supports collisions between user geometry, so
+    /// an `INVALID_ARGUMENT` error is returned if either scene contains
+    /// other types of geometry. The callback is called with batches of
+    /// colliding primitives from Embree's worker threads. The collisions
+    /// are found by overlapping the primitive bounds, exact tests can be
+    /// done on top, e.g. with `collide::triangles_overlap` for user
+    /// geometry made of triangles.
+    pub fn collide<F>(&self, other: &CommittedScene, f: F) -> Result<()>
+    where
+        F: Fn(&[Collision]) + Sync,
+    {
+        for scene in [self.scene, other.scene].iter() {
+            if let Some((id, _)) = scene.iter().find(|(_, g)| !matches!(g, Geometry::User(_))) {
+                return Err(EmbreeError::new(
+                    Error::INVALID_ARGUMENT,
+                    &format!(
+                        "collide only supports user geometry, geometry {} is not",
+                        id
+                    ),
+                ));
+            }
+        }
+        let callback: &CollideFn = &f;
+        self.scene.device.clear_error();
+        unsafe {
+            rtcCollide(
+                self.scene.handle,
+                other.scene.handle,
+                Some(collide::collide_function),
+                &callback as *const &CollideFn as *mut raw::c_void,
+            );
+        }
+        self.scene.device.get_error()
+    }
+    /// Compose the transform from the object space of a hit to world space
+    /// by walking the hit's instance ID stack `inst_ids`, see `Hit::instance_ids`,
+    /// from the outermost instance in this scene inwards. Returns `None` if
+    /// an ID is not an instance in the scene at its level.
+    pub fn instance_to_world(&self, inst_ids: &[u32], time: f32) -> Option<Mat4> {
+        let mut transform = Mat4::identity();
+        let mut scene = self;
+        for &id in inst_ids.iter().take_while(|&&id| id != u32::MAX) {
+            match scene.scene.get_geometry(id) {
+                Some(Geometry::Instance(inst)) => {
+                    transform = transform * inst.transform(time);
+                    scene = inst.scene;
+                }
+                _ => return None,
+            }
+        }
+        Some(transform)
+    }
+    pub fn bounds(&self) -> Bounds {
+        let mut bounds = Bounds::empty();
         unsafe {
             rtcGetSceneBounds(self.handle(), &mut bounds as *mut RTCBounds);
         }
         bounds
     }
+    /// Get the bounds of the scene at the start and end of the time range,
+    /// which bound the linear motion of all the geometry in the scene.
+    pub fn linear_bounds(&self) -> LinearBounds {
+        let mut bounds = LinearBounds {
+            bounds0: Bounds::empty(),
+            bounds1: Bounds::empty(),
+        };
+        unsafe {
+            rtcGetSceneLinearBounds(self.handle(), &mut bounds as *mut LinearBounds);
+        }
+        bounds
+    }
     /// Get the underlying handle to the scene, e.g. for passing it to
     /// native code or ISPC kernels.
     pub unsafe fn handle(&self) -> RTCScene {
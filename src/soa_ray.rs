@@ -4,6 +4,8 @@ use std::u32;
 
 use ultraviolet::Vec3;
 
+use crate::MAX_INSTANCE_LEVEL_COUNT;
+
 pub trait SoARay {
     fn org(&self, i: usize) -> Vec3;
     fn set_org(&mut self, i: usize, o: Vec3);
@@ -44,8 +46,23 @@ pub trait SoAHit {
     fn geom_id(&self, i: usize) -> u32;
     fn set_geom_id(&mut self, i: usize, id: u32);
 
-    fn inst_id(&self, i: usize) -> u32;
-    fn set_inst_id(&mut self, i: usize, id: u32);
+    /// Get the ID of the instance at `level` of the instance stack of hit `i`,
+    /// where level 0 is the outermost instance.
+    fn inst_id_at(&self, i: usize, level: usize) -> u32;
+    fn set_inst_id_at(&mut self, i: usize, level: usize, id: u32);
+
+    fn inst_id(&self, i: usize) -> u32 {
+        self.inst_id_at(i, 0)
+    }
+    fn set_inst_id(&mut self, i: usize, id: u32) {
+        self.set_inst_id_at(i, 0, id);
+    }
+    /// Get the number of instances hit `i` is nested in
+    fn instance_depth(&self, i: usize) -> usize {
+        (0..MAX_INSTANCE_LEVEL_COUNT)
+            .take_while(|&l| self.inst_id_at(i, l) != u32::MAX)
+            .count()
+    }
 
     fn hit(&self, i: usize) -> bool {
         self.geom_id(i) != u32::MAX
@@ -247,6 +264,12 @@ impl<'a, T: SoAHit + 'a> SoAHitRef<'a, T> {
     pub fn inst_id(&self) -> u32 {
         self.hit.inst_id(self.idx)
     }
+    pub fn inst_id_at(&self, level: usize) -> u32 {
+        self.hit.inst_id_at(self.idx, level)
+    }
+    pub fn instance_depth(&self) -> usize {
+        self.hit.instance_depth(self.idx)
+    }
     pub fn hit(&self) -> bool {
         self.hit.hit(self.idx)
     }
@@ -342,6 +365,18 @@ impl<'a, T: SoAHit + 'a> SoAHitRefMut<'a, T> {
         let hit = unsafe { self.hit.as_mut().expect("should never be null!") };
         hit.set_inst_id(self.idx, id);
     }
+    pub fn inst_id_at(&self, level: usize) -> u32 {
+        let hit = unsafe { self.hit.as_ref().expect("should never be null!") };
+        hit.inst_id_at(self.idx, level)
+    }
+    pub fn set_inst_id_at(&mut self, level: usize, id: u32) {
+        let hit = unsafe { self.hit.as_mut().expect("should never be null!") };
+        hit.set_inst_id_at(self.idx, level, id);
+    }
+    pub fn instance_depth(&self) -> usize {
+        let hit = unsafe { self.hit.as_ref().expect("should never be null!") };
+        hit.instance_depth(self.idx)
+    }
     pub fn hit(&self) -> bool {
         let hit = unsafe { self.hit.as_ref().expect("should never be null!") };
         hit.hit(self.idx)
@@ -1,17 +1,47 @@
+use std::iter;
+
 use ultraviolet::Vec3;
 
-use crate::buffer::Buffer;
+use crate::buffer::{update_all, Buffer};
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::displacement::{self, DisplacementArgs};
+use crate::error::Result;
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, geometry_data,
+    is_committed, mark_changed, new_geometry,
+};
 use crate::sys::*;
+use crate::vertex_attribute::{self, VertexAttribute};
 use crate::{BufferType, Format, GeometryType, SubdivisionMode};
 
 pub struct SubdivMesh<'a> {
-    device: &'a Device,
+    pub(crate) device: &'a Device,
     pub(crate) handle: RTCGeometry,
     pub vertex_buffer: Buffer<'a, Vec3>,
+    /// The vertex buffers for the time steps after the first of an animated mesh
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
+    /// The vertex indices of each face's edges, for the first topology
     pub index_buffer: Buffer<'a, u32>,
+    /// The number of edges of each face
     pub face_buffer: Buffer<'a, u32>,
+    /// The index buffers of the topologies after the first, added with
+    /// `add_topology`, e.g. for face-varying UVs
+    pub topology_index_buffers: Vec<Buffer<'a, u32>>,
+    /// The pairs of vertices of the creased edges, added with `add_edge_creases`
+    pub edge_crease_index_buffer: Option<Buffer<'a, [u32; 2]>>,
+    /// The crease weight of each creased edge, `f32::INFINITY` for a sharp edge
+    pub edge_crease_weight_buffer: Option<Buffer<'a, f32>>,
+    /// The creased vertices, added with `add_vertex_creases`
+    pub vertex_crease_index_buffer: Option<Buffer<'a, u32>>,
+    /// The crease weight of each creased vertex
+    pub vertex_crease_weight_buffer: Option<Buffer<'a, f32>>,
+    /// The faces which are holes, added with `add_holes`
+    pub hole_buffer: Option<Buffer<'a, u32>>,
+    /// The tessellation level of each edge, added with `add_levels`
+    pub level_buffer: Option<Buffer<'a, f32>>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    /// or `add_face_varying_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
 }
 
 impl<'a> SubdivMesh<'a> {
@@ -23,59 +53,512 @@ impl<'a> SubdivMesh<'a> {
         subdiv_mode: SubdivisionMode,
         subdiv_level: f32,
     ) -> SubdivMesh<'a> {
-        let h = unsafe { rtcNewGeometry(device.handle, GeometryType::SUBDIVISION) };
-        let mut vertex_buffer = Buffer::new(device, num_verts);
-        let mut index_buffer = Buffer::new(device, num_edges);
-        let mut face_buffer = Buffer::new(device, num_faces);
+        SubdivMesh::try_unanimated(
+            device,
+            num_faces,
+            num_edges,
+            num_verts,
+            subdiv_mode,
+            subdiv_level,
+        )
+        .expect("Failed to create SubdivMesh")
+    }
+    pub fn try_unanimated(
+        device: &'a Device,
+        num_faces: usize,
+        num_edges: usize,
+        num_verts: usize,
+        subdiv_mode: SubdivisionMode,
+        subdiv_level: f32,
+    ) -> Result<SubdivMesh<'a>> {
+        SubdivMesh::try_animated(
+            device,
+            num_faces,
+            num_edges,
+            num_verts,
+            subdiv_mode,
+            subdiv_level,
+            1,
+        )
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur,
+    /// panics if the mesh can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
+        device: &'a Device,
+        num_faces: usize,
+        num_edges: usize,
+        num_verts: usize,
+        subdiv_mode: SubdivisionMode,
+        subdiv_level: f32,
+        time_steps: u32,
+    ) -> SubdivMesh<'a> {
+        SubdivMesh::try_animated(
+            device,
+            num_faces,
+            num_edges,
+            num_verts,
+            subdiv_mode,
+            subdiv_level,
+            time_steps,
+        )
+        .expect("Failed to create SubdivMesh")
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur. The
+    /// vertices of the first time step are in `vertex_buffer` and the
+    /// following ones in `motion_vertex_buffers`.
+    pub fn try_animated(
+        device: &'a Device,
+        num_faces: usize,
+        num_edges: usize,
+        num_verts: usize,
+        subdiv_mode: SubdivisionMode,
+        subdiv_level: f32,
+        time_steps: u32,
+    ) -> Result<SubdivMesh<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let face_buffer = Buffer::try_new(device, num_faces)?;
+        let index_buffer = Buffer::try_new(device, num_edges)?;
+        SubdivMesh::with_buffers(
+            device,
+            vertex_buffer,
+            motion_vertex_buffers,
+            face_buffer,
+            index_buffer,
+            subdiv_mode,
+            subdiv_level,
+        )
+    }
+    /// Create a mesh using existing vertex, face and index buffers, e.g.
+    /// buffers sharing the user's memory made with `Buffer::shared` or
+    /// views of a larger buffer made with `Buffer::view`.
+    pub fn from_buffers(
+        device: &'a Device,
+        vertex_buffer: Buffer<'a, Vec3>,
+        face_buffer: Buffer<'a, u32>,
+        index_buffer: Buffer<'a, u32>,
+        subdiv_mode: SubdivisionMode,
+        subdiv_level: f32,
+    ) -> Result<SubdivMesh<'a>> {
+        SubdivMesh::with_buffers(
+            device,
+            vertex_buffer,
+            Vec::new(),
+            face_buffer,
+            index_buffer,
+            subdiv_mode,
+            subdiv_level,
+        )
+    }
+    fn with_buffers(
+        device: &'a Device,
+        mut vertex_buffer: Buffer<'a, Vec3>,
+        mut motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
+        mut face_buffer: Buffer<'a, u32>,
+        mut index_buffer: Buffer<'a, u32>,
+        subdiv_mode: SubdivisionMode,
+        subdiv_level: f32,
+    ) -> Result<SubdivMesh<'a>> {
+        let num_verts = vertex_buffer.len();
+        let time_steps = motion_vertex_buffers.len() as u32 + 1;
+        let h = unsafe { new_geometry(device, GeometryType::SUBDIVISION)? };
         unsafe {
-            rtcSetGeometryBuffer(
+            attach_buffer(h, &mut vertex_buffer, BufferType::VERTEX, 0, Format::FLOAT3);
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
                 h,
+                &mut motion_vertex_buffers,
                 BufferType::VERTEX,
-                0,
                 Format::FLOAT3,
-                vertex_buffer.handle,
-                0,
-                12,
                 num_verts,
             );
-            vertex_buffer.set_attachment(h, BufferType::VERTEX, 0);
-
-            rtcSetGeometryBuffer(
-                h,
-                BufferType::FACE,
+            attach_buffer(h, &mut face_buffer, BufferType::FACE, 0, Format::UINT);
+            attach_buffer(h, &mut index_buffer, BufferType::INDEX, 0, Format::UINT);
+            rtcSetGeometrySubdivisionMode(h, 0, subdiv_mode);
+            rtcSetGeometryTessellationRate(h, subdiv_level);
+            check_new_geometry(device, h)?;
+        }
+        Ok(SubdivMesh {
+            device,
+            handle: h,
+            vertex_buffer,
+            motion_vertex_buffers,
+            index_buffer,
+            face_buffer,
+            topology_index_buffers: Vec::new(),
+            edge_crease_index_buffer: None,
+            edge_crease_weight_buffer: None,
+            vertex_crease_index_buffer: None,
+            vertex_crease_weight_buffer: None,
+            hole_buffer: None,
+            level_buffer: None,
+            vertex_attributes: Vec::new(),
+        })
+    }
+    /// Add buffers for `num_creases` creased edges, filled through
+    /// `edge_crease_index_buffer` and `edge_crease_weight_buffer`.
+    pub fn add_edge_creases(&mut self, num_creases: usize) -> Result<()> {
+        let mut indices = Buffer::try_new(self.device, num_creases)?;
+        let mut weights = Buffer::try_new(self.device, num_creases)?;
+        unsafe {
+            attach_buffer(
+                self.handle,
+                &mut indices,
+                BufferType::EDGE_CREASE_INDEX,
                 0,
-                Format::UINT,
-                face_buffer.handle,
+                Format::UINT2,
+            );
+            attach_buffer(
+                self.handle,
+                &mut weights,
+                BufferType::EDGE_CREASE_WEIGHT,
                 0,
-                4,
-                num_faces,
+                Format::FLOAT,
             );
-            face_buffer.set_attachment(h, BufferType::FACE, 0);
-
-            rtcSetGeometryBuffer(
-                h,
-                BufferType::INDEX,
+        }
+        self.device.get_error()?;
+        self.edge_crease_index_buffer = Some(indices);
+        self.edge_crease_weight_buffer = Some(weights);
+        Ok(())
+    }
+    /// Add buffers for `num_creases` creased vertices, filled through
+    /// `vertex_crease_index_buffer` and `vertex_crease_weight_buffer`.
+    pub fn add_vertex_creases(&mut self, num_creases: usize) -> Result<()> {
+        let mut indices = Buffer::try_new(self.device, num_creases)?;
+        let mut weights = Buffer::try_new(self.device, num_creases)?;
+        unsafe {
+            attach_buffer(
+                self.handle,
+                &mut indices,
+                BufferType::VERTEX_CREASE_INDEX,
                 0,
                 Format::UINT,
-                index_buffer.handle,
+            );
+            attach_buffer(
+                self.handle,
+                &mut weights,
+                BufferType::VERTEX_CREASE_WEIGHT,
                 0,
-                4,
-                num_edges,
+                Format::FLOAT,
             );
-            index_buffer.set_attachment(h, BufferType::INDEX, 0);
         }
+        self.device.get_error()?;
+        self.vertex_crease_index_buffer = Some(indices);
+        self.vertex_crease_weight_buffer = Some(weights);
+        Ok(())
+    }
+    /// Add a buffer for the IDs of `num_holes` faces which are holes in
+    /// the surface, filled through `hole_buffer`.
+    pub fn add_holes(&mut self, num_holes: usize) -> Result<()> {
+        let mut holes = Buffer::try_new(self.device, num_holes)?;
         unsafe {
-            rtcSetGeometrySubdivisionMode(h, 0, subdiv_mode);
-            rtcSetGeometryTessellationRate(h, subdiv_level);
+            attach_buffer(self.handle, &mut holes, BufferType::HOLE, 0, Format::UINT);
         }
-        SubdivMesh {
-            device: device,
-            handle: h,
-            vertex_buffer: vertex_buffer,
-            index_buffer: index_buffer,
-            face_buffer: face_buffer,
+        self.device.get_error()?;
+        self.hole_buffer = Some(holes);
+        Ok(())
+    }
+    /// Add a buffer with the tessellation level of each edge in the index
+    /// buffer, filled through `level_buffer`, which is used instead of the
+    /// mesh's tessellation rate. Shared edges must have the same level
+    /// in both faces to avoid cracks.
+    pub fn add_levels(&mut self) -> Result<()> {
+        let mut levels = Buffer::try_new(self.device, self.index_buffer.len())?;
+        unsafe {
+            attach_buffer(
+                self.handle,
+                &mut levels,
+                BufferType::LEVEL,
+                0,
+                Format::FLOAT,
+            );
+        }
+        self.device.get_error()?;
+        self.level_buffer = Some(levels);
+        Ok(())
+    }
+    /// Add a topology with its own index buffer over the same faces and
+    /// `subdiv_mode`, returning its ID. The index buffer is filled through
+    /// `topology_index_buffers[id - 1]`, and vertex attributes can be
+    /// indexed by the topology with `add_face_varying_attribute`.
+    pub fn add_topology(&mut self, subdiv_mode: SubdivisionMode) -> Result<u32> {
+        let topology = self.topology_index_buffers.len() as u32 + 1;
+        let mut indices = Buffer::try_new(self.device, self.index_buffer.len())?;
+        unsafe {
+            rtcSetGeometryTopologyCount(self.handle, topology + 1);
+            attach_buffer(
+                self.handle,
+                &mut indices,
+                BufferType::INDEX,
+                topology,
+                Format::UINT,
+            );
+            rtcSetGeometrySubdivisionMode(self.handle, topology, subdiv_mode);
         }
+        self.device.get_error()?;
+        self.topology_index_buffers.push(indices);
+        Ok(topology)
+    }
+    /// Add a vertex attribute with `components` floats for each of
+    /// `num_values` values, indexed by the index buffer of `topology`
+    /// instead of the vertex indices, e.g. for UVs with seams. Returns the
+    /// attribute's slot.
+    pub fn add_face_varying_attribute(
+        &mut self,
+        components: u32,
+        num_values: usize,
+        topology: u32,
+    ) -> Result<u32> {
+        let slot = vertex_attribute::add_vertex_attribute(
+            self.device,
+            self.handle,
+            &mut self.vertex_attributes,
+            num_values,
+            components,
+        )?;
+        unsafe {
+            rtcSetGeometryVertexAttributeTopology(self.handle, slot, topology);
+        }
+        self.device.get_error()?;
+        Ok(slot)
+    }
+    /// Set the boundary handling of `topology`, 0 being the mesh's first
+    pub fn set_subdivision_mode(&mut self, topology: u32, subdiv_mode: SubdivisionMode) {
+        unsafe {
+            rtcSetGeometrySubdivisionMode(self.handle, topology, subdiv_mode);
+            mark_changed(self.handle);
+        }
+    }
+    /// Set the tessellation rate used for edges without a level
+    pub fn set_tessellation_rate(&mut self, rate: f32) {
+        unsafe {
+            rtcSetGeometryTessellationRate(self.handle, rate);
+            mark_changed(self.handle);
+        }
+    }
+    /// Set a callback to displace the tessellated surface, e.g. for
+    /// displacement mapping. The callback is called from Embree's build
+    /// threads with batches of vertices and their `u, v` on the patch and
+    /// surface normals, and moves their positions. `bound` is the maximum
+    /// distance the callback may move a vertex. Embree doesn't know about
+    /// the bound, displacements beyond it are clamped by this crate after
+    /// the callback returns, keeping the displaced surface within `bound`
+    /// of the limit surface. The mesh has to be committed
+    /// again for the change to take effect.
+    pub fn set_displacement_function<F>(&mut self, bound: f32, displacement: F)
+    where
+        F: Fn(&mut DisplacementArgs) + Sync + 'a,
+    {
+        assert!(bound >= 0.0, "displacement bound must not be negative");
+        unsafe {
+            let data = geometry_data(self.handle);
+            data.displacement = Some(Box::new(displacement));
+            data.displacement_bound = bound;
+            rtcSetGeometryDisplacementFunction(
+                self.handle,
+                Some(displacement::displacement_function),
+            );
+            mark_changed(self.handle);
+        }
+    }
+    /// Remove the displacement callback
+    pub fn clear_displacement_function(&mut self) {
+        unsafe {
+            rtcSetGeometryDisplacementFunction(self.handle, None);
+            geometry_data(self.handle).displacement = None;
+            mark_changed(self.handle);
+        }
+    }
+    /// Get the first half-edge of `face`, the half-edges of a face are
+    /// stored consecutively in the index buffer starting at this one.
+    /// Panics if the mesh hasn't been committed.
+    pub fn first_half_edge(&self, face: u32) -> HalfEdge<'_, 'a> {
+        self.check_committed();
+        assert!(
+            (face as usize) < self.face_buffer.len(),
+            "Face {} out of bounds",
+            face
+        );
+        let id = unsafe { rtcGetGeometryFirstHalfEdge(self.handle, face) };
+        HalfEdge { mesh: self, id }
+    }
+    /// Get the half-edge `id`, which is its index in the index buffer.
+    /// Panics if the mesh hasn't been committed.
+    pub fn half_edge(&self, id: u32) -> HalfEdge<'_, 'a> {
+        self.check_committed();
+        assert!(
+            (id as usize) < self.index_buffer.len(),
+            "Half-edge {} out of bounds",
+            id
+        );
+        HalfEdge { mesh: self, id }
+    }
+    fn check_committed(&self) {
+        assert!(
+            unsafe { is_committed(self.handle) },
+            "SubdivMesh must be committed to query its topology"
+        );
+    }
+    /// Update the buffers changed since the mesh was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | self.index_buffer.update()
+            | self.face_buffer.update()
+            | update_all(&self.topology_index_buffers)
+            | update_all(&self.edge_crease_index_buffer)
+            | update_all(&self.edge_crease_weight_buffer)
+            | update_all(&self.vertex_crease_index_buffer)
+            | update_all(&self.vertex_crease_weight_buffer)
+            | update_all(&self.hole_buffer)
+            | update_all(&self.level_buffer)
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
     }
 }
 
 unsafe impl<'a> Sync for SubdivMesh<'a> {}
+
+/// A half-edge of a committed `SubdivMesh`, which can be used to walk the
+/// edges of its faces and find the neighbouring faces. Each edge of a face
+/// is a half-edge from the vertex at its index in the index buffer to the
+/// next vertex of the face.
+#[derive(Copy, Clone)]
+pub struct HalfEdge<'m, 'a> {
+    mesh: &'m SubdivMesh<'a>,
+    id: u32,
+}
+
+impl<'m, 'a> HalfEdge<'m, 'a> {
+    /// Get the ID of the half-edge, which is its index in the index buffer
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// Get the face the half-edge belongs to
+    pub fn face(&self) -> u32 {
+        unsafe { rtcGetGeometryFace(self.mesh.handle, self.id) }
+    }
+    /// Get the vertex the half-edge starts at
+    pub fn vertex(&self) -> u32 {
+        self.mesh.index_buffer.get(self.id as usize)
+    }
+    /// Get the next half-edge around the face
+    pub fn next(&self) -> HalfEdge<'m, 'a> {
+        let id = unsafe { rtcGetGeometryNextHalfEdge(self.mesh.handle, self.id) };
+        HalfEdge {
+            mesh: self.mesh,
+            id,
+        }
+    }
+    /// Get the previous half-edge around the face
+    pub fn prev(&self) -> HalfEdge<'m, 'a> {
+        let id = unsafe { rtcGetGeometryPreviousHalfEdge(self.mesh.handle, self.id) };
+        HalfEdge {
+            mesh: self.mesh,
+            id,
+        }
+    }
+    /// Get the half-edge going the opposite way along the same edge in the
+    /// neighbouring face, or `None` if the edge is on the mesh's border
+    pub fn opposite(&self) -> Option<HalfEdge<'m, 'a>> {
+        self.opposite_in(0)
+    }
+    /// Get the opposite half-edge in the index buffer of `topology`,
+    /// see `opposite`
+    pub fn opposite_in(&self, topology: u32) -> Option<HalfEdge<'m, 'a>> {
+        // Embree returns the half-edge itself for border edges
+        let id = unsafe { rtcGetGeometryOppositeHalfEdge(self.mesh.handle, topology, self.id) };
+        if id == self.id {
+            None
+        } else {
+            Some(HalfEdge {
+                mesh: self.mesh,
+                id,
+            })
+        }
+    }
+    pub fn is_border(&self) -> bool {
+        self.opposite().is_none()
+    }
+    /// Get the face on the other side of the edge, if it's not a border
+    pub fn neighbour_face(&self) -> Option<u32> {
+        self.opposite().map(|e| e.face())
+    }
+    /// Get an iterator over the half-edges of the face, starting at this one
+    pub fn face_loop(&self) -> impl Iterator<Item = HalfEdge<'m, 'a>> {
+        let first = self.id;
+        iter::successors(Some(*self), move |e| {
+            let next = e.next();
+            if next.id == first {
+                None
+            } else {
+                Some(next)
+            }
+        })
+    }
+}
+
+impl<'m, 'a> PartialEq for HalfEdge<'m, 'a> {
+    fn eq(&self, other: &HalfEdge) -> bool {
+        self.mesh.handle == other.mesh.handle && self.id == other.id
+    }
+}
+
+#[test]
+fn test_half_edge_walk() {
+    use crate::geometry::Geometry;
+
+    let device = Device::new();
+    // Two quads side by side, sharing the edge between vertices 1 and 4
+    // 3 - 4 - 5
+    // | 0 | 1 |
+    // 0 - 1 - 2
+    let mut mesh = SubdivMesh::unanimated(&device, 2, 8, 6, SubdivisionMode::SMOOTH_BOUNDARY, 1.0);
+    {
+        let mut verts = mesh.vertex_buffer.map_mut();
+        for (i, v) in verts.iter_mut().enumerate() {
+            *v = Vec3::new((i % 3) as f32, (i / 3) as f32, 0.0);
+        }
+    }
+    mesh.index_buffer
+        .as_mut_slice()
+        .copy_from_slice(&[0, 1, 4, 3, 1, 2, 5, 4]);
+    mesh.face_buffer.as_mut_slice().copy_from_slice(&[4, 4]);
+    let mut geometry = Geometry::Subdiv(Box::new(mesh));
+    geometry.commit();
+    let mesh = match geometry {
+        Geometry::Subdiv(ref mesh) => mesh,
+        _ => unreachable!(),
+    };
+
+    for face in 0..2 {
+        let first = mesh.first_half_edge(face);
+        assert_eq!(first.id(), face * 4);
+        let mut edge = first;
+        for _ in 0..4 {
+            assert_eq!(edge.face(), face);
+            assert!(edge.next().prev() == edge);
+            edge = edge.next();
+        }
+        assert!(edge == first);
+        assert_eq!(first.face_loop().count(), 4);
+    }
+
+    // The shared edge goes 1 -> 4 in face 0 and 4 -> 1 in face 1
+    let shared = mesh.half_edge(1);
+    assert_eq!(shared.vertex(), 1);
+    let opposite = shared.opposite().unwrap();
+    assert_eq!(opposite.id(), 7);
+    assert_eq!(opposite.face(), 1);
+    assert_eq!(opposite.vertex(), 4);
+    assert!(opposite.opposite().unwrap() == shared);
+    assert_eq!(shared.neighbour_face(), Some(1));
+
+    let border = mesh.half_edge(0);
+    assert!(border.is_border());
+    assert_eq!(border.neighbour_face(), None);
+}
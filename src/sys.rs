@@ -5,7 +5,11 @@ pub const RTC_VERSION_MINOR: u32 = 12;
 pub const RTC_VERSION_PATCH: u32 = 1;
 pub const RTC_VERSION: u32 = 31201;
 pub const RTC_VERSION_STRING: &'static [u8; 7usize] = b"3.12.1\0";
+#[cfg(not(feature = "multi-level-instancing"))]
 pub const RTC_MAX_INSTANCE_LEVEL_COUNT: u32 = 1;
+// Must match the EMBREE_MAX_INSTANCE_LEVEL_COUNT Embree was built with
+#[cfg(feature = "multi-level-instancing")]
+pub const RTC_MAX_INSTANCE_LEVEL_COUNT: u32 = 8;
 pub const RTC_MIN_WIDTH: u32 = 0;
 pub const RTC_MAX_TIME_STEP_COUNT: u32 = 129;
 pub type size_t = usize;
@@ -372,8 +376,11 @@ pub type RTCFilterFunctionN =
 pub struct RTCIntersectContext {
     pub flags: RTCIntersectContextFlags,
     pub filter: RTCFilterFunctionN,
-    pub instID: [::std::os::raw::c_uint; 1usize],
+    #[cfg(feature = "multi-level-instancing")]
+    pub instStackSize: ::std::os::raw::c_uint,
+    pub instID: [::std::os::raw::c_uint; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCIntersectContext() {
     assert_eq!(
@@ -718,11 +725,12 @@ pub struct RTCPointQueryN {
 #[repr(align(16))]
 #[derive(Debug, Copy, Clone)]
 pub struct RTCPointQueryContext {
-    pub world2inst: [[f32; 16usize]; 1usize],
-    pub inst2world: [[f32; 16usize]; 1usize],
-    pub instID: [::std::os::raw::c_uint; 1usize],
+    pub world2inst: [[f32; 16usize]; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
+    pub inst2world: [[f32; 16usize]; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
+    pub instID: [::std::os::raw::c_uint; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
     pub instStackSize: ::std::os::raw::c_uint,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCPointQueryContext() {
     assert_eq!(
@@ -1168,8 +1176,9 @@ pub struct RTCHit {
     pub v: f32,
     pub primID: ::std::os::raw::c_uint,
     pub geomID: ::std::os::raw::c_uint,
-    pub instID: [::std::os::raw::c_uint; 1usize],
+    pub instID: [::std::os::raw::c_uint; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCHit() {
     assert_eq!(
@@ -1260,6 +1269,7 @@ pub struct RTCRayHit {
     pub ray: RTCRay,
     pub hit: RTCHit,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCRayHit() {
     assert_eq!(
@@ -1454,8 +1464,9 @@ pub struct RTCHit4 {
     pub v: [f32; 4usize],
     pub primID: [::std::os::raw::c_uint; 4usize],
     pub geomID: [::std::os::raw::c_uint; 4usize],
-    pub instID: [[::std::os::raw::c_uint; 4usize]; 1usize],
+    pub instID: [[::std::os::raw::c_uint; 4usize]; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCHit4() {
     assert_eq!(
@@ -1556,6 +1567,7 @@ pub struct RTCRayHit4 {
     pub ray: RTCRay4,
     pub hit: RTCHit4,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCRayHit4() {
     assert_eq!(
@@ -1750,8 +1762,9 @@ pub struct RTCHit8 {
     pub v: [f32; 8usize],
     pub primID: [::std::os::raw::c_uint; 8usize],
     pub geomID: [::std::os::raw::c_uint; 8usize],
-    pub instID: [[::std::os::raw::c_uint; 8usize]; 1usize],
+    pub instID: [[::std::os::raw::c_uint; 8usize]; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCHit8() {
     assert_eq!(
@@ -1852,6 +1865,7 @@ pub struct RTCRayHit8 {
     pub ray: RTCRay8,
     pub hit: RTCHit8,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCRayHit8() {
     assert_eq!(
@@ -2046,8 +2060,9 @@ pub struct RTCHit16 {
     pub v: [f32; 16usize],
     pub primID: [::std::os::raw::c_uint; 16usize],
     pub geomID: [::std::os::raw::c_uint; 16usize],
-    pub instID: [[::std::os::raw::c_uint; 16usize]; 1usize],
+    pub instID: [[::std::os::raw::c_uint; 16usize]; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCHit16() {
     assert_eq!(
@@ -2148,6 +2163,7 @@ pub struct RTCRayHit16 {
     pub ray: RTCRay16,
     pub hit: RTCHit16,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCRayHit16() {
     assert_eq!(
@@ -2340,8 +2356,9 @@ pub struct RTCHitNp {
     pub v: *mut f32,
     pub primID: *mut ::std::os::raw::c_uint,
     pub geomID: *mut ::std::os::raw::c_uint,
-    pub instID: [*mut ::std::os::raw::c_uint; 1usize],
+    pub instID: [*mut ::std::os::raw::c_uint; RTC_MAX_INSTANCE_LEVEL_COUNT as usize],
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCHitNp() {
     assert_eq!(
@@ -2441,6 +2458,7 @@ pub struct RTCRayHitNp {
     pub ray: RTCRayNp,
     pub hit: RTCHitNp,
 }
+#[cfg(not(feature = "multi-level-instancing"))]
 #[test]
 fn bindgen_test_layout_RTCRayHitNp() {
     assert_eq!(
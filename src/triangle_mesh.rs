@@ -1,54 +1,145 @@
 use ultraviolet::Vec3;
 
-use crate::buffer::Buffer;
+use crate::buffer::{update_all, Buffer};
+use crate::collide;
 use crate::device::Device;
-use crate::geometry::Geometry;
+use crate::error::Result;
+use crate::geometry::{
+    attach_buffer, attach_time_step_buffers, check_new_geometry, check_time_steps, new_geometry,
+};
+use crate::point_query;
 use crate::sys::*;
+use crate::vertex_attribute::VertexAttribute;
 use crate::{BufferType, Format, GeometryType};
 
 pub struct TriangleMesh<'a> {
-    device: &'a Device,
+    pub(crate) device: &'a Device,
     pub(crate) handle: RTCGeometry,
     pub vertex_buffer: Buffer<'a, Vec3>,
+    /// The vertex buffers for the time steps after the first of an animated mesh
+    pub motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
     pub index_buffer: Buffer<'a, [u32; 3]>,
+    /// The vertex attribute buffers, added with `Geometry::add_vertex_attribute`
+    pub vertex_attributes: Vec<VertexAttribute<'a>>,
 }
 
 impl<'a> TriangleMesh<'a> {
     pub fn unanimated(device: &'a Device, num_tris: usize, num_verts: usize) -> TriangleMesh<'a> {
-        let h = unsafe { rtcNewGeometry(device.handle, GeometryType::TRIANGLE) };
-        let mut vertex_buffer = Buffer::new(device, num_verts);
-        let mut index_buffer = Buffer::new(device, num_tris);
+        TriangleMesh::try_unanimated(device, num_tris, num_verts)
+            .expect("Failed to create TriangleMesh")
+    }
+    pub fn try_unanimated(
+        device: &'a Device,
+        num_tris: usize,
+        num_verts: usize,
+    ) -> Result<TriangleMesh<'a>> {
+        TriangleMesh::try_animated(device, num_tris, num_verts, 1)
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur,
+    /// panics if the mesh can't be created. See `try_animated` for a
+    /// fallible version.
+    pub fn animated(
+        device: &'a Device,
+        num_tris: usize,
+        num_verts: usize,
+        time_steps: u32,
+    ) -> TriangleMesh<'a> {
+        TriangleMesh::try_animated(device, num_tris, num_verts, time_steps)
+            .expect("Failed to create TriangleMesh")
+    }
+    /// Create a mesh with `time_steps` vertex buffers for motion blur. The
+    /// vertices of the first time step are in `vertex_buffer` and the
+    /// following ones in `motion_vertex_buffers`.
+    pub fn try_animated(
+        device: &'a Device,
+        num_tris: usize,
+        num_verts: usize,
+        time_steps: u32,
+    ) -> Result<TriangleMesh<'a>> {
+        check_time_steps(time_steps)?;
+        let vertex_buffer = Buffer::try_new(device, num_verts)?;
+        let motion_vertex_buffers = (1..time_steps)
+            .map(|_| Buffer::try_new(device, num_verts))
+            .collect::<Result<Vec<_>>>()?;
+        let index_buffer = Buffer::try_new(device, num_tris)?;
+        TriangleMesh::with_buffers(device, vertex_buffer, motion_vertex_buffers, index_buffer)
+    }
+    /// Create a mesh using existing vertex and index buffers, e.g. buffers
+    /// sharing the user's memory made with `Buffer::shared` or views of
+    /// a larger buffer made with `Buffer::view`.
+    pub fn from_buffers(
+        device: &'a Device,
+        vertex_buffer: Buffer<'a, Vec3>,
+        index_buffer: Buffer<'a, [u32; 3]>,
+    ) -> Result<TriangleMesh<'a>> {
+        TriangleMesh::with_buffers(device, vertex_buffer, Vec::new(), index_buffer)
+    }
+    fn with_buffers(
+        device: &'a Device,
+        mut vertex_buffer: Buffer<'a, Vec3>,
+        mut motion_vertex_buffers: Vec<Buffer<'a, Vec3>>,
+        mut index_buffer: Buffer<'a, [u32; 3]>,
+    ) -> Result<TriangleMesh<'a>> {
+        let num_verts = vertex_buffer.len();
+        let time_steps = motion_vertex_buffers.len() as u32 + 1;
+        let h = unsafe { new_geometry(device, GeometryType::TRIANGLE)? };
         unsafe {
-            rtcSetGeometryBuffer(
+            attach_buffer(h, &mut vertex_buffer, BufferType::VERTEX, 0, Format::FLOAT3);
+            rtcSetGeometryTimeStepCount(h, time_steps);
+            attach_time_step_buffers(
                 h,
+                &mut motion_vertex_buffers,
                 BufferType::VERTEX,
-                0,
                 Format::FLOAT3,
-                vertex_buffer.handle,
-                0,
-                12,
                 num_verts,
             );
-            vertex_buffer.set_attachment(h, BufferType::VERTEX, 0);
-
-            rtcSetGeometryBuffer(
-                h,
-                BufferType::INDEX,
-                0,
-                Format::UINT3,
-                index_buffer.handle,
-                0,
-                12,
-                num_tris,
-            );
-            index_buffer.set_attachment(h, BufferType::INDEX, 0);
+            attach_buffer(h, &mut index_buffer, BufferType::INDEX, 0, Format::UINT3);
+            check_new_geometry(device, h)?;
         }
-        TriangleMesh {
+        Ok(TriangleMesh {
             device: device,
             handle: h,
             vertex_buffer: vertex_buffer,
+            motion_vertex_buffers,
             index_buffer: index_buffer,
-        }
+            vertex_attributes: Vec::new(),
+        })
+    }
+    /// Get the vertices of the triangle `prim_id`
+    pub fn triangle(&self, prim_id: u32) -> [Vec3; 3] {
+        let idx = self.index_buffer.get(prim_id as usize);
+        [
+            self.vertex_buffer.get(idx[0] as usize),
+            self.vertex_buffer.get(idx[1] as usize),
+            self.vertex_buffer.get(idx[2] as usize),
+        ]
+    }
+    /// Find the closest point to `p` on the triangle `prim_id`, e.g. for
+    /// use in a `CommittedScene::point_query` callback.
+    pub fn closest_point(&self, prim_id: u32, p: Vec3) -> Vec3 {
+        let v = self.triangle(prim_id);
+        point_query::closest_point_triangle(p, v[0], v[1], v[2])
+    }
+    /// Test if the triangle `prim_id` overlaps the triangle `other_prim_id`
+    /// of `other`. `CommittedScene::collide` only supports user geometry,
+    /// so to test the pairs it finds the meshes' triangles must be
+    /// attached to the collided scenes as `UserGeometry` with the same
+    /// primitive IDs, while the meshes themselves can't be collided.
+    pub fn triangle_overlaps(
+        &self,
+        prim_id: u32,
+        other: &TriangleMesh,
+        other_prim_id: u32,
+    ) -> bool {
+        collide::triangles_overlap(&self.triangle(prim_id), &other.triangle(other_prim_id))
+    }
+    /// Update the buffers changed since the mesh was last committed,
+    /// returns true if it has to be committed again.
+    pub(crate) fn update_buffers(&self) -> bool {
+        self.vertex_buffer.update()
+            | update_all(&self.motion_vertex_buffers)
+            | self.index_buffer.update()
+            | update_all(self.vertex_attributes.iter().map(|a| &a.buffer))
     }
 }
 
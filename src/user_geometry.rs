@@ -0,0 +1,388 @@
+use std::os::raw;
+use std::{f32, slice};
+
+use ultraviolet::Vec3;
+
+use crate::bounds::Bounds;
+use crate::catch_unwind_abort;
+use crate::device::Device;
+use crate::error::Result;
+use crate::geometry::{check_new_geometry, new_geometry, GeometryData};
+use crate::ray::IntersectContext;
+use crate::ray_stream::{HitNRef, RayNRef};
+use crate::soa_ray::{SoAHit, SoARay};
+use crate::sys::*;
+use crate::{GeometryType, MAX_INSTANCE_LEVEL_COUNT};
+
+/// Implemented by user defined primitives (analytic spheres, SDFs, etc.)
+/// to provide their bounds and ray intersection to Embree. The callbacks
+/// are run on Embree's build and traversal threads, so the implementor
+/// must be `Sync`. Panics in the callbacks abort the process, since they
+/// can't unwind back through Embree.
+pub trait UserPrimitives: Sync {
+    /// Compute the bounds of primitive `prim_id` at the time step `time_step`
+    fn bounds(&self, prim_id: u32, time_step: u32) -> Bounds;
+    /// Intersect the valid rays in `args` with the primitive `args.prim_id()`,
+    /// recording hits with `IntersectArgs::report_hit`.
+    fn intersect(&self, args: &mut IntersectArgs);
+    /// Test the valid rays in `args` for occlusion by the primitive
+    /// `args.prim_id()`, marking occluded rays with `OccludedArgs::report_hit`.
+    fn occluded(&self, args: &mut OccludedArgs);
+}
+
+/// The arguments passed to `UserPrimitives::intersect`, wrapping
+/// `RTCIntersectFunctionNArguments`.
+pub struct IntersectArgs<'a> {
+    args: *const RTCIntersectFunctionNArguments,
+    valid: &'a [i32],
+    prim_id: u32,
+    geom_id: u32,
+    context: &'a IntersectContext,
+    pub ray: RayNRef<'a>,
+    pub hit: HitNRef<'a>,
+}
+
+impl<'a> IntersectArgs<'a> {
+    pub fn prim_id(&self) -> u32 {
+        self.prim_id
+    }
+    pub fn geom_id(&self) -> u32 {
+        self.geom_id
+    }
+    /// The number of rays in the packet, including invalid ones
+    pub fn len(&self) -> usize {
+        self.valid.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.valid.is_empty()
+    }
+    /// Check if ray `i` is active and should be intersected
+    pub fn valid(&self, i: usize) -> bool {
+        self.valid[i] != 0
+    }
+    pub fn context(&self) -> &IntersectContext {
+        self.context
+    }
+    /// Record a hit for ray `i` at distance `t` along the ray. The hit is
+    /// only recorded if the ray is valid, `t` is within the ray's
+    /// `[tnear, tfar]` interval and the hit isn't rejected by the
+    /// geometry's intersection filter or the context filter. Returns true
+    /// if the hit was recorded.
+    pub fn report_hit(&mut self, i: usize, t: f32, u: f32, v: f32, normal: Vec3) -> bool {
+        if !self.valid(i) || t < self.ray.tnear(i) || t > self.ray.tfar(i) {
+            return false;
+        }
+        let n = self.len();
+        let mut hit = PotentialHit::new(n, i, self.prim_id, self.geom_id, self.context);
+        hit.set(u, v, normal);
+        // The filters see the ray's tfar set to the potential hit
+        let tfar = self.ray.tfar(i);
+        self.ray.set_tfar(i, t);
+        let accepted = unsafe {
+            let args = &*self.args;
+            let filter_args = hit.filter_args(
+                args.geometryUserPtr,
+                args.context,
+                args.rayhit as *mut RTCRayN,
+            );
+            rtcFilterIntersection(args, &filter_args);
+            hit.valid[i] != 0
+        };
+        if !accepted {
+            self.ray.set_tfar(i, tfar);
+            return false;
+        }
+        // The filters may have changed the hit, so copy it back from the potential hit
+        let potential = hit.hit_mut();
+        let (u, v) = potential.uv(i);
+        self.hit.set_normal(i, potential.normal(i));
+        self.hit.set_u(i, u);
+        self.hit.set_v(i, v);
+        self.hit.set_prim_id(i, potential.prim_id(i));
+        self.hit.set_geom_id(i, potential.geom_id(i));
+        for level in 0..MAX_INSTANCE_LEVEL_COUNT {
+            self.hit
+                .set_inst_id_at(i, level, potential.inst_id_at(i, level));
+        }
+        true
+    }
+}
+
+/// The arguments passed to `UserPrimitives::occluded`, wrapping
+/// `RTCOccludedFunctionNArguments`.
+pub struct OccludedArgs<'a> {
+    args: *const RTCOccludedFunctionNArguments,
+    valid: &'a [i32],
+    prim_id: u32,
+    geom_id: u32,
+    context: &'a IntersectContext,
+    pub ray: RayNRef<'a>,
+}
+
+impl<'a> OccludedArgs<'a> {
+    pub fn prim_id(&self) -> u32 {
+        self.prim_id
+    }
+    pub fn geom_id(&self) -> u32 {
+        self.geom_id
+    }
+    /// The number of rays in the packet, including invalid ones
+    pub fn len(&self) -> usize {
+        self.valid.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.valid.is_empty()
+    }
+    /// Check if ray `i` is active and should be tested
+    pub fn valid(&self, i: usize) -> bool {
+        self.valid[i] != 0
+    }
+    pub fn context(&self) -> &IntersectContext {
+        self.context
+    }
+    /// Mark ray `i` as occluded by a hit at distance `t` along the ray,
+    /// this is done by setting its `tfar` to -inf. The ray is only marked
+    /// if it's valid, `t` is within the ray's `[tnear, tfar]` interval and
+    /// the hit isn't rejected by the geometry's occlusion filter or the
+    /// context filter. Returns true if the ray was marked occluded.
+    pub fn report_hit(&mut self, i: usize, t: f32, u: f32, v: f32, normal: Vec3) -> bool {
+        if !self.valid(i) || t < self.ray.tnear(i) || t > self.ray.tfar(i) {
+            return false;
+        }
+        let n = self.len();
+        let mut hit = PotentialHit::new(n, i, self.prim_id, self.geom_id, self.context);
+        hit.set(u, v, normal);
+        let tfar = self.ray.tfar(i);
+        self.ray.set_tfar(i, t);
+        let accepted = unsafe {
+            let args = &*self.args;
+            let filter_args = hit.filter_args(args.geometryUserPtr, args.context, args.ray);
+            rtcFilterOcclusion(args, &filter_args);
+            hit.valid[i] != 0
+        };
+        self.ray
+            .set_tfar(i, if accepted { f32::NEG_INFINITY } else { tfar });
+        accepted
+    }
+}
+
+/// The SoA `RTCHitN` packet holding a potential hit reported by user
+/// geometry for ray `i`, which is passed to the filters with only ray
+/// `i` marked valid.
+struct PotentialHit {
+    i: usize,
+    valid: Vec<i32>,
+    hit: Vec<u32>,
+}
+
+impl PotentialHit {
+    fn new(
+        n: usize,
+        i: usize,
+        prim_id: u32,
+        geom_id: u32,
+        context: &IntersectContext,
+    ) -> PotentialHit {
+        let mut valid = vec![0; n];
+        valid[i] = -1;
+        let mut potential = PotentialHit {
+            i,
+            valid,
+            hit: vec![u32::MAX; n * (7 + MAX_INSTANCE_LEVEL_COUNT)],
+        };
+        let mut hit = potential.hit_mut();
+        hit.set_prim_id(i, prim_id);
+        hit.set_geom_id(i, geom_id);
+        for (level, &id) in context.instID.iter().enumerate() {
+            hit.set_inst_id_at(i, level, id);
+        }
+        potential
+    }
+    fn set(&mut self, u: f32, v: f32, normal: Vec3) {
+        let i = self.i;
+        let mut hit = self.hit_mut();
+        hit.set_normal(i, normal);
+        hit.set_u(i, u);
+        hit.set_v(i, v);
+    }
+    fn hit_mut(&mut self) -> HitNRef<'_> {
+        unsafe { HitNRef::from_raw(self.hit.as_mut_ptr() as *mut RTCHitN, self.valid.len()) }
+    }
+    unsafe fn filter_args(
+        &mut self,
+        user_ptr: *mut raw::c_void,
+        context: *mut RTCIntersectContext,
+        ray: *mut RTCRayN,
+    ) -> RTCFilterFunctionNArguments {
+        RTCFilterFunctionNArguments {
+            valid: self.valid.as_mut_ptr(),
+            geometryUserPtr: user_ptr,
+            context,
+            ray,
+            hit: self.hit.as_mut_ptr() as *mut RTCHitN,
+            N: self.valid.len() as u32,
+        }
+    }
+}
+
+/// A geometry made of user defined primitives. The `UserPrimitives`
+/// data is owned by the geometry and released after the Embree
+/// geometry is, so the callbacks can never outlive it. Like the other
+/// geometry types, it's released when dropped as a `Geometry::User`.
+pub struct UserGeometry<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) handle: RTCGeometry,
+    primitives: Box<dyn UserPrimitives + 'a>,
+}
+
+impl<'a> UserGeometry<'a> {
+    /// Create a geometry of `num_prims` user primitives, panics if the
+    /// geometry can't be created. See `try_new` for a fallible version.
+    pub fn new<T: UserPrimitives + 'a>(
+        device: &'a Device,
+        num_prims: usize,
+        primitives: T,
+    ) -> UserGeometry<'a> {
+        UserGeometry::try_new(device, num_prims, primitives).expect("Failed to create UserGeometry")
+    }
+    pub fn try_new<T: UserPrimitives + 'a>(
+        device: &'a Device,
+        num_prims: usize,
+        primitives: T,
+    ) -> Result<UserGeometry<'a>> {
+        let h = unsafe { new_geometry(device, GeometryType::USER)? };
+        let primitives = Box::new(primitives);
+        let mut data = Box::new(GeometryData::new());
+        data.primitives = &*primitives as *const T as *const raw::c_void;
+        let user_ptr = Box::into_raw(data) as *mut raw::c_void;
+        unsafe {
+            rtcSetGeometryUserPrimitiveCount(h, num_prims as u32);
+            rtcSetGeometryUserData(h, user_ptr);
+            rtcSetGeometryBoundsFunction(h, Some(bounds_function::<T>), user_ptr);
+            rtcSetGeometryIntersectFunction(h, Some(intersect_function::<T>));
+            rtcSetGeometryOccludedFunction(h, Some(occluded_function::<T>));
+            check_new_geometry(device, h)?;
+        }
+        Ok(UserGeometry {
+            device,
+            handle: h,
+            primitives,
+        })
+    }
+    /// Get the user primitives being ray traced by this geometry
+    pub fn primitives(&self) -> &dyn UserPrimitives {
+        &*self.primitives
+    }
+}
+
+unsafe impl<'a> Sync for UserGeometry<'a> {}
+
+unsafe fn user_primitives<'a, T>(user_ptr: *mut raw::c_void) -> &'a T {
+    let data = &*(user_ptr as *const GeometryData);
+    &*(data.primitives as *const T)
+}
+
+unsafe extern "C" fn bounds_function<T: UserPrimitives>(args: *const RTCBoundsFunctionArguments) {
+    let args = &*args;
+    let primitives = user_primitives::<T>(args.geometryUserPtr);
+    let bounds = catch_unwind_abort(|| primitives.bounds(args.primID, args.timeStep));
+    *args.bounds_o = bounds;
+}
+
+unsafe extern "C" fn intersect_function<T: UserPrimitives>(
+    args: *const RTCIntersectFunctionNArguments,
+) {
+    let args = &*args;
+    let primitives = user_primitives::<T>(args.geometryUserPtr);
+    let n = args.N as usize;
+    let mut intersect_args = IntersectArgs {
+        args,
+        valid: slice::from_raw_parts(args.valid, n),
+        prim_id: args.primID,
+        geom_id: args.geomID,
+        context: &*args.context,
+        ray: RayNRef::from_raw(args.rayhit as *mut RTCRayN, n),
+        hit: HitNRef::from_rayhit(args.rayhit, n),
+    };
+    catch_unwind_abort(|| primitives.intersect(&mut intersect_args));
+}
+
+unsafe extern "C" fn occluded_function<T: UserPrimitives>(
+    args: *const RTCOccludedFunctionNArguments,
+) {
+    let args = &*args;
+    let primitives = user_primitives::<T>(args.geometryUserPtr);
+    let n = args.N as usize;
+    let mut occluded_args = OccludedArgs {
+        args,
+        valid: slice::from_raw_parts(args.valid, n),
+        prim_id: args.primID,
+        geom_id: args.geomID,
+        context: &*args.context,
+        ray: RayNRef::from_raw(args.ray, n),
+    };
+    catch_unwind_abort(|| primitives.occluded(&mut occluded_args));
+}
+
+#[test]
+fn test_filter_rejects_user_hit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{Geometry, IntersectContext, Ray, RayHit, Scene};
+
+    /// A square in the z = 0 plane which counts the hits it reports and
+    /// the ones recorded
+    struct Square<'c> {
+        reported: &'c AtomicUsize,
+        recorded: &'c AtomicUsize,
+    }
+    impl<'c> UserPrimitives for Square<'c> {
+        fn bounds(&self, _: u32, _: u32) -> Bounds {
+            Bounds::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0))
+        }
+        fn intersect(&self, args: &mut IntersectArgs) {
+            for i in 0..args.len() {
+                let t = -args.ray.org(i).z / args.ray.dir(i).z;
+                self.reported.fetch_add(1, Ordering::Relaxed);
+                if args.report_hit(i, t, 0.0, 0.0, Vec3::unit_z()) {
+                    self.recorded.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        fn occluded(&self, _: &mut OccludedArgs) {}
+    }
+
+    let device = Device::new();
+    let reported = AtomicUsize::new(0);
+    let recorded = AtomicUsize::new(0);
+    let filtered = AtomicUsize::new(0);
+    let square = Square {
+        reported: &reported,
+        recorded: &recorded,
+    };
+    let mut geometry = Geometry::User(UserGeometry::new(&device, 1, square));
+    geometry.set_intersect_filter(|args| {
+        for i in 0..args.len() {
+            if args.valid(i) {
+                filtered.fetch_add(1, Ordering::Relaxed);
+                args.reject(i);
+            }
+        }
+    });
+    geometry.commit();
+    let mut scene = Scene::new(&device);
+    scene.attach_geometry(geometry);
+    let scene = scene.commit();
+
+    let mut ray = RayHit::new(Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::unit_z()));
+    scene.intersect(&mut IntersectContext::coherent(), &mut ray);
+    // The hit was reported and passed to the filter, which dropped it
+    assert!(reported.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        filtered.load(Ordering::Relaxed),
+        reported.load(Ordering::Relaxed)
+    );
+    assert_eq!(recorded.load(Ordering::Relaxed), 0);
+    assert!(!ray.hit.hit());
+    assert_eq!(ray.ray.tfar, f32::INFINITY);
+}
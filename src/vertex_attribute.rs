@@ -0,0 +1,196 @@
+use std::ptr;
+
+use crate::buffer::Buffer;
+use crate::device::Device;
+use crate::error::{EmbreeError, Result};
+use crate::sys::*;
+use crate::{BufferType, Error, Format};
+
+const FLOAT_FORMATS: [Format; 16] = [
+    Format::FLOAT,
+    Format::FLOAT2,
+    Format::FLOAT3,
+    Format::FLOAT4,
+    Format::FLOAT5,
+    Format::FLOAT6,
+    Format::FLOAT7,
+    Format::FLOAT8,
+    Format::FLOAT9,
+    Format::FLOAT10,
+    Format::FLOAT11,
+    Format::FLOAT12,
+    Format::FLOAT13,
+    Format::FLOAT14,
+    Format::FLOAT15,
+    Format::FLOAT16,
+];
+
+/// A vertex attribute buffer storing `components` floats per vertex,
+/// e.g. 2 for UVs or 3 for shading normals or colors.
+pub struct VertexAttribute<'a> {
+    pub buffer: Buffer<'a, f32>,
+    components: u32,
+}
+
+impl<'a> VertexAttribute<'a> {
+    pub fn components(&self) -> u32 {
+        self.components
+    }
+    /// Get the attribute of vertex `i`
+    pub fn get(&self, i: usize) -> &[f32] {
+        let n = self.components as usize;
+        &self.buffer.as_slice()[i * n..(i + 1) * n]
+    }
+    pub fn get_mut(&mut self, i: usize) -> &mut [f32] {
+        let n = self.components as usize;
+        &mut self.buffer.as_mut_slice()[i * n..(i + 1) * n]
+    }
+}
+
+/// An attribute interpolated over a primitive with `interpolate`, along
+/// with its first and second derivatives with respect to u and v.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpolated {
+    pub value: Vec<f32>,
+    pub du: Vec<f32>,
+    pub dv: Vec<f32>,
+    pub dudu: Vec<f32>,
+    pub dvdv: Vec<f32>,
+    pub dudv: Vec<f32>,
+}
+
+impl Interpolated {
+    fn new(value_count: usize) -> Interpolated {
+        Interpolated {
+            value: vec![0.0; value_count],
+            du: vec![0.0; value_count],
+            dv: vec![0.0; value_count],
+            dudu: vec![0.0; value_count],
+            dvdv: vec![0.0; value_count],
+            dudv: vec![0.0; value_count],
+        }
+    }
+}
+
+/// Allocate a new vertex attribute buffer with `components` floats for
+/// each of the `num_verts` vertices and attach it to the next vertex
+/// attribute slot of the geometry `handle`. Returns the slot of the attribute.
+pub(crate) fn add_vertex_attribute<'a>(
+    device: &'a Device,
+    handle: RTCGeometry,
+    attributes: &mut Vec<VertexAttribute<'a>>,
+    num_verts: usize,
+    components: u32,
+) -> Result<u32> {
+    if components == 0 || components > 16 {
+        return Err(EmbreeError::new(
+            Error::INVALID_ARGUMENT,
+            &format!(
+                "vertex attributes must have 1 to 16 components, got {}",
+                components
+            ),
+        ));
+    }
+    let slot = attributes.len() as u32;
+    let mut buffer = Buffer::try_new(device, num_verts * components as usize)?;
+    unsafe {
+        rtcSetGeometryVertexAttributeCount(handle, slot + 1);
+        rtcSetGeometryBuffer(
+            handle,
+            BufferType::VERTEX_ATTRIBUTE,
+            slot,
+            FLOAT_FORMATS[components as usize - 1],
+            buffer.handle,
+            0,
+            4 * components as usize,
+            num_verts,
+        );
+    }
+    buffer.set_attachment(handle, BufferType::VERTEX_ATTRIBUTE, slot);
+    device.get_error()?;
+    attributes.push(VertexAttribute { buffer, components });
+    Ok(slot)
+}
+
+/// Interpolate `value_count` floats of the buffer `buf_type, slot` at the
+/// hit `u, v` on the primitive `prim_id` of the committed geometry `handle`.
+pub(crate) fn interpolate(
+    handle: RTCGeometry,
+    prim_id: u32,
+    u: f32,
+    v: f32,
+    buf_type: BufferType,
+    slot: u32,
+    value_count: u32,
+) -> Interpolated {
+    let mut res = Interpolated::new(value_count as usize);
+    let args = RTCInterpolateArguments {
+        geometry: handle,
+        primID: prim_id,
+        u,
+        v,
+        bufferType: buf_type,
+        bufferSlot: slot,
+        P: res.value.as_mut_ptr(),
+        dPdu: res.du.as_mut_ptr(),
+        dPdv: res.dv.as_mut_ptr(),
+        ddPdudu: res.dudu.as_mut_ptr(),
+        ddPdvdv: res.dvdv.as_mut_ptr(),
+        ddPdudv: res.dudv.as_mut_ptr(),
+        valueCount: value_count,
+    };
+    unsafe {
+        rtcInterpolate(&args);
+    }
+    res
+}
+
+/// Interpolate `value_count` floats of the buffer `buf_type, slot` for
+/// a batch of hits, using Embree's vectorized `rtcInterpolateN`.
+pub(crate) fn interpolate_n(
+    handle: RTCGeometry,
+    prim_ids: &[u32],
+    u: &[f32],
+    v: &[f32],
+    buf_type: BufferType,
+    slot: u32,
+    value_count: u32,
+) -> Vec<Interpolated> {
+    assert_eq!(prim_ids.len(), u.len());
+    assert_eq!(prim_ids.len(), v.len());
+    let n = prim_ids.len();
+    // Embree writes the results in SoA layout, value i of hit k is at i * n + k
+    let mut soa = Interpolated::new(n * value_count as usize);
+    let args = RTCInterpolateNArguments {
+        geometry: handle,
+        valid: ptr::null(),
+        primIDs: prim_ids.as_ptr(),
+        u: u.as_ptr(),
+        v: v.as_ptr(),
+        N: n as u32,
+        bufferType: buf_type,
+        bufferSlot: slot,
+        P: soa.value.as_mut_ptr(),
+        dPdu: soa.du.as_mut_ptr(),
+        dPdv: soa.dv.as_mut_ptr(),
+        ddPdudu: soa.dudu.as_mut_ptr(),
+        ddPdvdv: soa.dvdv.as_mut_ptr(),
+        ddPdudv: soa.dudv.as_mut_ptr(),
+        valueCount: value_count,
+    };
+    unsafe {
+        rtcInterpolateN(&args);
+    }
+    let gather =
+        |v: &[f32], k: usize| -> Vec<f32> { v.iter().skip(k).step_by(n).cloned().collect() };
+    (0..n)
+        .map(|k| Interpolated {
+            value: gather(&soa.value, k),
+            du: gather(&soa.du, k),
+            dv: gather(&soa.dv, k),
+            dudu: gather(&soa.dudu, k),
+            dvdv: gather(&soa.dvdv, k),
+            dudv: gather(&soa.dudv, k),
+        })
+        .collect()
+}